@@ -1,7 +1,12 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure()
-        .build_client(false)
-        .compile(&["./schemas/tfplugin6.0.proto"], &["./schemas"])?;
+    tonic_build::configure().build_client(false).compile(
+        &[
+            "./schemas/tfplugin6.0.proto",
+            "./schemas/grpc_controller.proto",
+            "./schemas/grpc_stdio.proto",
+        ],
+        &["./schemas"],
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,214 @@
+//! A provider that keeps its resources in memory, as a starting point for new providers.
+//!
+//! Build it with `cargo build --example in_memory` and point Terraform at the binary with
+//! a `dev_overrides` entry in `~/.terraformrc`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use rust_terraform_provider::{
+    cty::MaybeUnknown,
+    logging, model,
+    resource::{DataSource, Diagnostics, Registry, Resource},
+    server::{serve, Provider},
+    util::random_hex_suffix,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+type Store = Arc<Mutex<HashMap<String, String>>>;
+
+model! {
+    /// In-memory provider
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct MemoryProviderConfig {}
+}
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct EntryConfig {
+        /// Key of the entry. Changing it creates a new entry.
+        #[schema(required, replace)]
+        pub key: MaybeUnknown<String>,
+
+        /// Value stored under the key.
+        #[schema(required)]
+        pub value: MaybeUnknown<String>,
+    }
+}
+
+model! {
+    /// An entry in the in-memory store
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct EntryState {
+        #[schema(flatten)]
+        #[serde(flatten)]
+        pub config: EntryConfig,
+
+        /// Random id assigned when the entry is created.
+        #[schema(optional, computed)]
+        pub id: MaybeUnknown<String>,
+    }
+}
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct EntriesConfig {}
+}
+
+model! {
+    /// All entries in the in-memory store
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct EntriesState {
+        /// Keys of all entries.
+        #[schema(computed)]
+        pub keys: Vec<String>,
+    }
+}
+
+struct EntryResource {
+    store: Store,
+}
+
+#[tonic::async_trait]
+impl Resource for EntryResource {
+    type Config = EntryConfig;
+    type State = EntryState;
+
+    fn config(state: &EntryState) -> &EntryConfig {
+        &state.config
+    }
+
+    fn plan_create(&self, config: EntryConfig) -> EntryState {
+        EntryState {
+            config,
+            id: MaybeUnknown::Unknown,
+        }
+    }
+
+    fn plan_update(&self, prior_state: EntryState, config: EntryConfig) -> EntryState {
+        EntryState {
+            config,
+            ..prior_state
+        }
+    }
+
+    async fn read(&self, state: EntryState) -> Result<Option<EntryState>> {
+        let store = self.store.lock().await;
+
+        Ok(store
+            .get(state.config.key.require("key")?)
+            .map(|value| EntryState {
+                config: EntryConfig {
+                    value: value.clone().into(),
+                    ..state.config.clone()
+                },
+                ..state
+            }))
+    }
+
+    async fn create(
+        &self,
+        planned_state: EntryState,
+        _diagnostics: &mut Diagnostics,
+    ) -> Result<EntryState> {
+        let mut store = self.store.lock().await;
+        store.insert(
+            planned_state.config.key.require("key")?.clone(),
+            planned_state.config.value.require("value")?.clone(),
+        );
+
+        Ok(EntryState {
+            id: random_hex_suffix(8).into(),
+            ..planned_state
+        })
+    }
+
+    async fn update(
+        &self,
+        _prior_state: EntryState,
+        planned_state: EntryState,
+        _diagnostics: &mut Diagnostics,
+    ) -> Result<EntryState> {
+        let mut store = self.store.lock().await;
+        store.insert(
+            planned_state.config.key.require("key")?.clone(),
+            planned_state.config.value.require("value")?.clone(),
+        );
+
+        Ok(planned_state)
+    }
+
+    async fn delete(&self, prior_state: EntryState, _diagnostics: &mut Diagnostics) -> Result<()> {
+        let mut store = self.store.lock().await;
+        store.remove(prior_state.config.key.require("key")?);
+
+        Ok(())
+    }
+}
+
+struct EntriesDataSource {
+    store: Store,
+}
+
+#[tonic::async_trait]
+impl DataSource for EntriesDataSource {
+    type Config = EntriesConfig;
+    type State = EntriesState;
+
+    async fn read(&self, _config: EntriesConfig) -> Result<EntriesState> {
+        let store = self.store.lock().await;
+
+        let mut keys: Vec<String> = store.keys().cloned().collect();
+        keys.sort();
+
+        Ok(EntriesState { keys })
+    }
+}
+
+struct MemoryProvider {
+    registry: Registry,
+}
+
+#[tonic::async_trait]
+impl Provider for MemoryProvider {
+    type Config = MemoryProviderConfig;
+
+    fn address(&self) -> &'static str {
+        "registry.terraform.io/example/memory"
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    async fn configure(&self, _config: MemoryProviderConfig) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init("provider.terraform-provider-memory");
+
+    let store = Store::default();
+
+    let provider = MemoryProvider {
+        registry: Registry::default()
+            .resource(
+                "memory_entry",
+                EntryResource {
+                    store: store.clone(),
+                },
+            )
+            .data_source("memory_entries", EntriesDataSource { store }),
+    };
+
+    serve(provider, CancellationToken::new()).await
+}
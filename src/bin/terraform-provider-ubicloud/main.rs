@@ -0,0 +1,35 @@
+mod provider;
+mod ubicloud;
+mod vm;
+
+use anyhow::Result;
+use rust_terraform_provider::{
+    logging,
+    server::{serve, serve_debug},
+};
+use tokio_util::sync::CancellationToken;
+
+use provider::UbicloudProvider;
+
+/// Module the provider's logs are tagged with in Terraform's output.
+const LOG_MODULE: &str = "provider.terraform-provider-ubicloud";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let debug = std::env::args().skip(1).any(|arg| arg == "--debug");
+
+    if debug {
+        logging::init_debug(LOG_MODULE);
+    } else {
+        logging::init(LOG_MODULE);
+    }
+
+    let shutdown = CancellationToken::new();
+    let provider = UbicloudProvider::new(shutdown.clone());
+
+    if debug {
+        serve_debug(provider, shutdown).await
+    } else {
+        serve(provider, shutdown).await
+    }
+}
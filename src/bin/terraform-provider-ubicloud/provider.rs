@@ -0,0 +1,99 @@
+use std::{fmt, sync::Arc};
+
+use rust_terraform_provider::{
+    cty::MaybeUnknown,
+    logging::REDACTED,
+    model,
+    resource::Registry,
+    server::{tf, Provider},
+};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    ubicloud::{Client as UbicloudClient, Credentials as UbicloudCredentials},
+    vm::{VmDataSource, VmResource, VmsDataSource},
+};
+
+model! {
+    /// Ubicloud provider
+    #[schema]
+    #[derive(Deserialize, Serialize)]
+    pub struct ProviderConfig {
+        /// Email for Ubicloud account used to provision resources
+        #[schema(required, sensitive)]
+        email: MaybeUnknown<String>,
+
+        /// Password for Ubicloud account used to provision resources
+        #[schema(required, sensitive)]
+        password: MaybeUnknown<String>,
+    }
+}
+
+impl fmt::Debug for ProviderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderConfig")
+            .field("email", &REDACTED)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+pub struct UbicloudProvider {
+    ubicloud: Arc<UbicloudClient>,
+    registry: Registry,
+}
+
+impl UbicloudProvider {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        let ubicloud = Arc::new(UbicloudClient::new(None));
+
+        let registry = Registry::default()
+            .resource("ubicloud_vm", VmResource::new(ubicloud.clone(), shutdown))
+            .data_source("ubicloud_vm", VmDataSource::new(ubicloud.clone()))
+            .data_source("ubicloud_vms", VmsDataSource::new(ubicloud.clone()));
+
+        Self { ubicloud, registry }
+    }
+}
+
+#[tonic::async_trait]
+impl Provider for UbicloudProvider {
+    type Config = ProviderConfig;
+
+    fn address(&self) -> &'static str {
+        "registry.terraform.io/ubicloud/ubicloud"
+    }
+
+    fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    fn provider_meta(&self) -> Option<tf::Schema> {
+        Some(tf::Schema {
+            version: 1,
+            block: Some(tf::schema::Block {
+                version: 1,
+                attributes: vec![],
+                block_types: vec![],
+                description: "Ubicloud terraform provider".to_string(),
+                description_kind: tf::StringKind::Markdown as i32,
+                deprecated: false,
+            }),
+        })
+    }
+
+    async fn configure(&self, config: ProviderConfig) -> anyhow::Result<()> {
+        // credentials that are not known yet during planning are set once they are, until
+        // then requests to the api fail for lack of credentials
+        if let (Some(email), Some(password)) =
+            (config.email.into_known(), config.password.into_known())
+        {
+            self.ubicloud
+                .set_credentials(UbicloudCredentials { email, password })
+                .await;
+        }
+
+        Ok(())
+    }
+}
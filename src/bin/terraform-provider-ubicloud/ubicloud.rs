@@ -1,10 +1,14 @@
+use std::fmt;
+
 use anyhow::{anyhow, bail, Result};
+use rust_terraform_provider::logging::REDACTED;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
 const UBICLOUD_BASE_URL: &str = "https://console.ubicloud.com/api";
+const UBICLOUD_BASE_URL_ENV: &str = "UBICLOUD_API_URL";
 
-#[derive(Debug, Clone, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
 pub enum VmState {
     #[serde(rename = "creating")]
     Creating,
@@ -16,7 +20,18 @@ pub enum VmState {
     Deleting,
 }
 
+impl std::fmt::Display for VmState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmState::Creating => write!(f, "creating"),
+            VmState::Running => write!(f, "running"),
+            VmState::Deleting => write!(f, "deleting"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 pub struct Vm {
     pub id: String,
     pub name: String,
@@ -49,23 +64,48 @@ pub struct VmCreateInput {
     pub enable_public_ipv4: bool,
 }
 
-#[derive(Debug)]
 pub struct Credentials {
     pub email: String,
     pub password: String,
 }
 
-#[derive(Debug)]
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("email", &REDACTED)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
 pub struct Client {
     client: reqwest::Client,
+    base_url: String,
     credentials: Mutex<Option<Credentials>>,
     token: Mutex<Option<String>>,
 }
 
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the credentials and the session token stay out of the logs
+        f.debug_struct("Client")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Client {
     pub fn new(credentials: Option<Credentials>) -> Self {
+        let base_url =
+            std::env::var(UBICLOUD_BASE_URL_ENV).unwrap_or_else(|_| UBICLOUD_BASE_URL.to_string());
+
+        Self::with_base_url(base_url, credentials)
+    }
+
+    pub fn with_base_url(base_url: impl Into<String>, credentials: Option<Credentials>) -> Self {
         Self {
             client: reqwest::Client::new(),
+            base_url: base_url.into(),
             credentials: Mutex::new(credentials),
             token: Mutex::new(None),
         }
@@ -86,16 +126,22 @@ impl Client {
 
         let credentials = credentials.as_ref().unwrap();
 
-        let client = reqwest::Client::new();
-        let url = format!(
-            "{}/login?login={}&password={}",
-            UBICLOUD_BASE_URL, credentials.email, credentials.password
-        );
-        let response = client
+        let url = format!("{}/login", self.base_url);
+
+        // the credentials go in the body, so they stay out of urls in errors and server logs
+        let input = serde_json::to_string(&serde_json::json!({
+            "login": credentials.email,
+            "password": credentials.password,
+        }))?;
+
+        let response = self
+            .client
             .post(&url)
             .header("Content-Type", "application/json")
+            .body(input)
             .send()
-            .await?;
+            .await
+            .map_err(reqwest::Error::without_url)?;
 
         if !response.status().is_success() {
             bail!("ubicloud login failed");
@@ -121,7 +167,6 @@ impl Client {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub async fn list_vm(&self, project_id: String, location: String) -> Result<Vec<Vm>> {
         self.ensure_auth().await?;
         let token = {
@@ -129,7 +174,8 @@ impl Client {
             token.clone().unwrap()
         };
 
-        let url = format!("{UBICLOUD_BASE_URL}/project/{project_id}/location/{location}/vm");
+        let base_url = &self.base_url;
+        let url = format!("{base_url}/project/{project_id}/location/{location}/vm");
 
         let response = self
             .client
@@ -161,7 +207,8 @@ impl Client {
             token.clone().unwrap()
         };
 
-        let url = format!("{UBICLOUD_BASE_URL}/project/{project_id}/location/{location}/vm/{name}");
+        let base_url = &self.base_url;
+        let url = format!("{base_url}/project/{project_id}/location/{location}/vm/{name}");
 
         let response = self
             .client
@@ -197,7 +244,8 @@ impl Client {
             token.clone().unwrap()
         };
 
-        let url = format!("{UBICLOUD_BASE_URL}/project/{project_id}/location/{location}/vm/{name}");
+        let base_url = &self.base_url;
+        let url = format!("{base_url}/project/{project_id}/location/{location}/vm/{name}");
 
         let response = self
             .client
@@ -226,7 +274,8 @@ impl Client {
             token.clone().unwrap()
         };
 
-        let url = format!("{UBICLOUD_BASE_URL}/project/{project_id}/location/{location}/vm");
+        let base_url = &self.base_url;
+        let url = format!("{base_url}/project/{project_id}/location/{location}/vm");
 
         let input = serde_json::to_string(&input)?;
 
@@ -254,3 +303,53 @@ impl Client {
         Ok(vm)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{body_json, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            email: "user@example.com".to_string(),
+            password: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_the_credentials_in_the_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .and(body_json(serde_json::json!({
+                "login": "user@example.com",
+                "password": "secret",
+            })))
+            .respond_with(ResponseTemplate::new(200).insert_header("authorization", "token"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_base_url(server.uri(), Some(credentials()));
+        client.login().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.query(), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_credentials_out_of_connection_errors() {
+        // nothing listens on the discard port
+        let client = Client::with_base_url("http://127.0.0.1:9", Some(credentials()));
+
+        let err = client.login().await.unwrap_err();
+
+        let err = format!("{err:#}");
+        assert!(!err.contains("secret"), "{err}");
+        assert!(!err.contains("user@example.com"), "{err}");
+    }
+}
@@ -0,0 +1,1317 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use rust_terraform_provider::{
+    cty::{MaybeUnknown, Refinements, Type},
+    model, push_diagnostic,
+    resource::{DataSource, Diagnostics, Resource},
+    schema::CtyType,
+    server::tf,
+    util::{parse_duration, random_hex_suffix, PollOutcome, Poller},
+};
+
+use crate::ubicloud::{Client as UbicloudClient, Vm, VmCreateInput, VmState};
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmResourceConfig {
+        /// Region where the VM will be created in. Current supported options are
+        /// `hetzner-hel1` or `hetzner-fsn1`. The Ubicloud API can't move a VM to another
+        /// region, so changing it replaces the VM.
+        #[schema(required, markdown, replace)]
+        pub region: MaybeUnknown<String>,
+
+        /// Project where the VM will be created in. The Ubicloud API can't move a VM to
+        /// another project, so changing it replaces the VM.
+        #[schema(required, markdown, replace)]
+        pub project_id: MaybeUnknown<String>,
+
+        /// Friendly name of the resource (used to compute the real name). Changing it does
+        /// not rename an existing VM.
+        #[schema(required)]
+        pub name: MaybeUnknown<String>,
+
+        /// Size of the VM. Current supported options are `standard-2`, `standard-4`,
+        /// `standard-8` and `standard-16`. The Ubicloud API can't resize a VM, so changing it
+        /// replaces the VM.
+        #[schema(required, markdown, replace)]
+        pub size: MaybeUnknown<String>,
+
+        /// Image to use for the VM. Current supported options are `ubuntu-jammy` and
+        /// `almalinux-9.1`. Changing it replaces the VM, the image is only used to create it.
+        #[schema(required, markdown, replace)]
+        pub image: MaybeUnknown<String>,
+
+        /// Linux user used when creating the VM. The Ubicloud API can't change it afterwards,
+        /// so changing it replaces the VM.
+        #[schema(required, replace)]
+        pub user: MaybeUnknown<String>,
+
+        /// SSH public key used when creating the VM. The Ubicloud API can't change it
+        /// afterwards, so changing it replaces the VM.
+        #[schema(required, sensitive, replace)]
+        pub public_key: MaybeUnknown<String>,
+
+        /// Whether to enable public IPv4 for the VM. Defaults to `false`. The Ubicloud API
+        /// can't add or remove the address of an existing VM, so changing it replaces the VM.
+        #[schema(optional, markdown, replace)]
+        pub enable_public_ipv4: Option<bool>,
+
+        /// Timeouts and poll intervals for VM provisioning.
+        #[schema(block)]
+        pub timeouts: Option<VmTimeouts>,
+    }
+}
+
+model! {
+    /// Timeouts and poll intervals for VM provisioning.
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone, Default)]
+    pub struct VmTimeouts {
+        /// How long to wait for the VM to start, like `30m`. Defaults to `20m`.
+        #[schema(optional, markdown)]
+        pub create: Option<String>,
+
+        /// How long to wait for the VM to be deleted, like `30m`. Defaults to `20m`.
+        #[schema(optional, markdown)]
+        pub delete: Option<String>,
+
+        /// Initial interval between VM state checks. Doubles after each check. Defaults to
+        /// `5s`.
+        #[schema(optional, markdown)]
+        pub poll_interval: Option<String>,
+
+        /// Upper bound for the interval between VM state checks. Defaults to `30s`.
+        #[schema(optional, markdown)]
+        pub max_poll_interval: Option<String>,
+    }
+}
+
+impl VmTimeouts {
+    const DEFAULT_CREATE: Duration = Duration::from_secs(20 * 60);
+    const DEFAULT_DELETE: Duration = Duration::from_secs(20 * 60);
+    const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+    const DEFAULT_MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+    fn durations(&self) -> [(&'static str, Option<&String>); 4] {
+        [
+            ("create", self.create.as_ref()),
+            ("delete", self.delete.as_ref()),
+            ("poll_interval", self.poll_interval.as_ref()),
+            ("max_poll_interval", self.max_poll_interval.as_ref()),
+        ]
+    }
+
+    fn parse(value: Option<&String>, default: Duration) -> Result<Duration> {
+        match value {
+            Some(value) => parse_duration(value),
+            None => Ok(default),
+        }
+    }
+
+    pub fn create(&self) -> Result<Duration> {
+        Self::parse(self.create.as_ref(), Self::DEFAULT_CREATE)
+    }
+
+    pub fn delete(&self) -> Result<Duration> {
+        Self::parse(self.delete.as_ref(), Self::DEFAULT_DELETE)
+    }
+
+    pub fn poller(&self, timeout: Duration, cancel: CancellationToken) -> Result<Poller> {
+        Ok(Poller::new(
+            timeout,
+            Self::parse(self.poll_interval.as_ref(), Self::DEFAULT_POLL_INTERVAL)?,
+            Self::parse(
+                self.max_poll_interval.as_ref(),
+                Self::DEFAULT_MAX_POLL_INTERVAL,
+            )?,
+            cancel,
+        ))
+    }
+}
+
+model! {
+    /// Ubicloud Virtual Machine
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmResourceState {
+        #[schema(flatten)]
+        #[serde(flatten)]
+        pub config: VmResourceConfig,
+
+        /// The real name of the VM in Ubicloud.
+        #[schema(optional, computed)]
+        pub vm_name: MaybeUnknown<String>,
+
+        /// Public IPv4 address of the VM.
+        #[schema(optional, computed)]
+        pub public_ipv4: MaybeUnknown<Option<String>>,
+
+        /// Public IPv6 address of the VM.
+        #[schema(optional, computed)]
+        pub public_ipv6: MaybeUnknown<Option<String>>,
+    }
+}
+
+impl VmResourceConfig {
+    /// Project and region of the VM, known once the VM gets created.
+    fn location(&self) -> Result<(String, String)> {
+        Ok((
+            self.project_id.require("project_id")?.clone(),
+            self.region.require("region")?.clone(),
+        ))
+    }
+}
+
+impl VmResourceState {
+    /// The real name of the VM, known once it was created.
+    fn vm_name(&self) -> Result<String> {
+        self.vm_name
+            .known()
+            .cloned()
+            .ok_or_else(|| anyhow!("the name of the vm is not known yet"))
+    }
+}
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmDataSourceConfig {
+        /// Region where the VM lives. Current supported options are `hetzner-hel1` or
+        /// `hetzner-fsn1`.
+        #[schema(required, markdown)]
+        pub region: String,
+
+        /// Project where the VM lives.
+        #[schema(required)]
+        pub project_id: String,
+
+        /// The real name of the VM in Ubicloud.
+        #[schema(required)]
+        pub name: String,
+    }
+}
+
+model! {
+    /// Existing Ubicloud Virtual Machine
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmDataSourceState {
+        #[schema(flatten)]
+        #[serde(flatten)]
+        pub config: VmDataSourceConfig,
+
+        /// Size of the VM.
+        #[schema(computed)]
+        pub size: String,
+
+        /// Linux user of the VM.
+        #[schema(computed)]
+        pub user: String,
+
+        /// Current state of the VM (`creating`, `running` or `deleting`).
+        #[schema(computed, markdown)]
+        pub state: VmState,
+
+        /// Public IPv4 address of the VM.
+        #[schema(computed)]
+        pub public_ipv4: Option<String>,
+
+        /// Public IPv6 address of the VM.
+        #[schema(computed)]
+        pub public_ipv6: Option<String>,
+    }
+}
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmsDataSourceConfig {
+        /// Region to list VMs from. Current supported options are `hetzner-hel1` or
+        /// `hetzner-fsn1`.
+        #[schema(required, markdown)]
+        pub region: String,
+
+        /// Project to list VMs from.
+        #[schema(required)]
+        pub project_id: String,
+
+        /// Only include VMs whose real name starts with this prefix.
+        #[schema(optional)]
+        pub name_prefix: Option<String>,
+
+        /// Only include VMs in this state (`creating`, `running` or `deleting`).
+        #[schema(optional, markdown)]
+        pub state: Option<VmState>,
+    }
+}
+
+model! {
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmsDataSourceItem {
+        #[schema(computed)]
+        pub name: String,
+
+        #[schema(computed)]
+        pub region: String,
+
+        #[schema(computed)]
+        pub size: String,
+
+        #[schema(computed)]
+        pub user: String,
+
+        #[schema(computed)]
+        pub state: VmState,
+
+        #[schema(computed)]
+        pub public_ipv4: Option<String>,
+
+        #[schema(computed)]
+        pub public_ipv6: Option<String>,
+    }
+}
+
+model! {
+    /// Existing Ubicloud Virtual Machines in a project and region
+    #[schema]
+    #[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
+    pub struct VmsDataSourceState {
+        #[schema(flatten)]
+        #[serde(flatten)]
+        pub config: VmsDataSourceConfig,
+
+        /// VMs matching the filters.
+        #[schema(computed)]
+        pub vms: Vec<VmsDataSourceItem>,
+    }
+}
+
+impl CtyType for VmState {
+    fn cty_type() -> Type {
+        Type::String
+    }
+}
+
+/// Result of creating a VM and waiting for it to start.
+pub enum Provisioning {
+    Ready(Vm),
+
+    /// The provider was asked to stop before the VM reached the running state.
+    Interrupted {
+        vm_name: String,
+        last_seen: Option<Vm>,
+    },
+
+    /// The VM was created, but waiting for it to reach the running state failed.
+    Failed {
+        vm_name: String,
+        last_seen: Option<Vm>,
+        error: anyhow::Error,
+    },
+}
+
+impl Provisioning {
+    /// Reports a VM that exists but is not known to be running. Terraform keeps the
+    /// returned state and marks the resource as tainted when one of these is an error.
+    pub fn push_diagnostics(&self, diagnostics: &mut Diagnostics) {
+        match self {
+            Provisioning::Ready(_) => {}
+            Provisioning::Interrupted { vm_name, .. } => {
+                push_diagnostic!(
+                    diagnostics,
+                    "provider stopped while creating vm",
+                    format!("vm `{vm_name}` was created but is not running yet"),
+                    tf::diagnostic::Severity::Warning
+                );
+            }
+            Provisioning::Failed { vm_name, error, .. } => {
+                push_diagnostic!(
+                    diagnostics,
+                    "vm did not become ready",
+                    format!(
+                        "vm `{vm_name}` was created but did not reach the running state: {error:#}"
+                    )
+                );
+            }
+        }
+    }
+
+    pub fn into_state(self, planned_state: VmResourceState) -> VmResourceState {
+        let (vm_name, last_seen) = match self {
+            Provisioning::Ready(vm) => (vm.name.clone(), Some(vm)),
+            Provisioning::Interrupted { vm_name, last_seen }
+            | Provisioning::Failed {
+                vm_name, last_seen, ..
+            } => (vm_name, last_seen),
+        };
+
+        match last_seen {
+            Some(vm) => VmResourceState {
+                vm_name: vm.name.into(),
+                public_ipv4: vm.ip4.into(),
+                public_ipv6: vm.ip6.into(),
+                ..planned_state
+            },
+            None => VmResourceState {
+                vm_name: vm_name.into(),
+                public_ipv4: None.into(),
+                public_ipv6: None.into(),
+                ..planned_state
+            },
+        }
+    }
+}
+
+/// Result of deleting a VM and waiting for it to disappear.
+pub enum Deletion {
+    Done,
+
+    /// The provider was asked to stop before the VM was gone.
+    Interrupted,
+}
+
+/// Random bytes in the suffix that makes the real name of a VM unique.
+const VM_NAME_SUFFIX_BYTES: usize = 3;
+
+/// The name a VM was configured with: its real name without the random suffix added on
+/// create. Names without such a suffix, like those of VMs created by hand, are kept whole.
+fn friendly_name(vm_name: &str) -> &str {
+    let is_suffix = |suffix: &str| {
+        // the two hex digits of each byte used to not be padded, so suffixes can be shorter
+        (VM_NAME_SUFFIX_BYTES..=VM_NAME_SUFFIX_BYTES * 2).contains(&suffix.len())
+            && suffix
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+
+    match vm_name.rsplit_once('-') {
+        Some((name, suffix)) if !name.is_empty() && is_suffix(suffix) => name,
+        _ => vm_name,
+    }
+}
+
+/// The `ubicloud_vm` resource.
+pub struct VmResource {
+    ubicloud: Arc<UbicloudClient>,
+    shutdown: CancellationToken,
+}
+
+impl VmResource {
+    pub fn new(ubicloud: Arc<UbicloudClient>, shutdown: CancellationToken) -> Self {
+        Self { ubicloud, shutdown }
+    }
+
+    async fn create_vm_and_wait(&self, config: &VmResourceConfig) -> Result<Provisioning> {
+        let timeouts = config.timeouts.clone().unwrap_or_default();
+        let timeout = timeouts.create()?;
+        let mut poller = timeouts.poller(timeout, self.shutdown.clone())?;
+
+        let (project_id, region) = config.location()?;
+        let vm_name = format!(
+            "{}-{}",
+            config.name.require("name")?,
+            random_hex_suffix(VM_NAME_SUFFIX_BYTES)
+        );
+
+        self.ubicloud
+            .create_vm(
+                project_id.clone(),
+                region.clone(),
+                VmCreateInput {
+                    name: vm_name.clone(),
+                    size: config.size.require("size")?.clone(),
+                    image: config.image.require("image")?.clone(),
+                    user: config.user.require("user")?.clone(),
+                    public_key: config.public_key.require("public_key")?.clone(),
+                    enable_public_ipv4: config.enable_public_ipv4.unwrap_or(false),
+                },
+            )
+            .await?;
+
+        // from here on the vm exists, so failures are reported alongside its name instead
+        // of as errors, to keep it tracked in state
+        let mut last_seen: Option<Vm> = None;
+
+        loop {
+            match poller.wait().await {
+                PollOutcome::Due => {}
+                PollOutcome::TimedOut => {
+                    let error = anyhow!(
+                        "timed out after {timeout:?}, last seen state: {}",
+                        last_seen
+                            .as_ref()
+                            .map_or("unknown".to_string(), |vm| vm.state.to_string())
+                    );
+
+                    return Ok(Provisioning::Failed {
+                        vm_name,
+                        last_seen,
+                        error,
+                    });
+                }
+                PollOutcome::Cancelled => {
+                    return Ok(Provisioning::Interrupted { vm_name, last_seen });
+                }
+            }
+
+            let vm = match self
+                .ubicloud
+                .get_vm(project_id.clone(), region.clone(), vm_name.clone())
+                .await
+            {
+                Ok(Some(vm)) => vm,
+                Ok(None) => {
+                    return Ok(Provisioning::Failed {
+                        vm_name,
+                        last_seen,
+                        error: anyhow!("vm disappeared while waiting for it to start"),
+                    });
+                }
+                Err(error) => {
+                    return Ok(Provisioning::Failed {
+                        vm_name,
+                        last_seen,
+                        error: error.context("failed to get vm"),
+                    });
+                }
+            };
+
+            if vm.state == VmState::Running {
+                return Ok(Provisioning::Ready(vm));
+            }
+
+            last_seen = Some(vm);
+        }
+    }
+
+    async fn delete_vm_and_wait(&self, state: &VmResourceState) -> Result<Deletion> {
+        let config = &state.config;
+        let (project_id, region) = config.location()?;
+        let vm_name = state.vm_name()?;
+
+        let timeouts = config.timeouts.clone().unwrap_or_default();
+        let timeout = timeouts.delete()?;
+        let mut poller = timeouts.poller(timeout, self.shutdown.clone())?;
+
+        self.ubicloud
+            .delete_vm(project_id.clone(), region.clone(), vm_name.clone())
+            .await?;
+
+        let mut last_state = None;
+
+        loop {
+            match poller.wait().await {
+                PollOutcome::Due => {}
+                PollOutcome::TimedOut => {
+                    bail!(
+                        "timed out after {timeout:?} waiting for vm `{}` to be deleted, last seen state: {}",
+                        vm_name,
+                        last_state.map_or("unknown".to_string(), |state: VmState| state.to_string())
+                    );
+                }
+                PollOutcome::Cancelled => return Ok(Deletion::Interrupted),
+            }
+
+            let vm = self
+                .ubicloud
+                .get_vm(project_id.clone(), region.clone(), vm_name.clone())
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to check whether vm `{}` was deleted, last seen state: {}",
+                        vm_name,
+                        last_state
+                            .as_ref()
+                            .map_or("unknown".to_string(), VmState::to_string)
+                    )
+                })?;
+
+            let Some(vm) = vm else {
+                return Ok(Deletion::Done);
+            };
+
+            last_state = Some(vm.state);
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Resource for VmResource {
+    type Config = VmResourceConfig;
+    type State = VmResourceState;
+
+    fn config(state: &VmResourceState) -> &VmResourceConfig {
+        &state.config
+    }
+
+    fn forces_replacement(
+        attribute: &str,
+        prior_state: &VmResourceState,
+        config: &VmResourceConfig,
+    ) -> bool {
+        let prior = &prior_state.config;
+
+        match attribute {
+            // the api doesn't return these, so they are empty for imported vms
+            "image" => prior.image != MaybeUnknown::Known(String::new()),
+            "public_key" => prior.public_key != MaybeUnknown::Known(String::new()),
+            // vms get no public ipv4 unless it is enabled
+            "enable_public_ipv4" => {
+                prior.enable_public_ipv4.unwrap_or(false)
+                    != config.enable_public_ipv4.unwrap_or(false)
+            }
+            _ => true,
+        }
+    }
+
+    fn validate(&self, config: &VmResourceConfig, diagnostics: &mut Diagnostics) {
+        let timeouts = config.timeouts.clone().unwrap_or_default();
+        for (name, value) in timeouts.durations() {
+            let Some(value) = value else {
+                continue;
+            };
+
+            if let Err(err) = parse_duration(value) {
+                diagnostics.diagnostics.push(tf::Diagnostic {
+                    severity: tf::diagnostic::Severity::Error as i32,
+                    summary: "invalid timeout".to_string(),
+                    detail: err.to_string(),
+                    attribute: Some(tf::AttributePath {
+                        steps: ["timeouts", name]
+                            .into_iter()
+                            .map(|name| tf::attribute_path::Step {
+                                selector: Some(tf::attribute_path::step::Selector::AttributeName(
+                                    name.into(),
+                                )),
+                            })
+                            .collect(),
+                    }),
+                });
+            }
+        }
+    }
+
+    fn plan_create(&self, config: VmResourceConfig) -> VmResourceState {
+        // the name gets a random suffix when the vm is created
+        let mut vm_name = Refinements::default().not_null();
+        if let Some(name) = config.name.known() {
+            vm_name = vm_name.string_prefix(format!("{name}-"));
+        }
+
+        VmResourceState {
+            config,
+            vm_name: MaybeUnknown::Refined(vm_name),
+            public_ipv4: MaybeUnknown::Unknown,
+            public_ipv6: MaybeUnknown::Unknown,
+        }
+    }
+
+    fn plan_update(
+        &self,
+        prior_state: VmResourceState,
+        config: VmResourceConfig,
+    ) -> VmResourceState {
+        VmResourceState {
+            config,
+            ..prior_state
+        }
+    }
+
+    async fn read(&self, state: VmResourceState) -> Result<Option<VmResourceState>> {
+        let (project_id, region) = state.config.location()?;
+
+        let vm = self
+            .ubicloud
+            .get_vm(project_id, region, state.vm_name()?)
+            .await
+            .context("failed to get vm")?;
+
+        let Some(vm) = vm else {
+            info!("vm {} no longer exists", state.vm_name);
+            return Ok(None);
+        };
+
+        let mut new_state = state;
+        new_state.config.size = vm.size.into();
+        new_state.config.user = vm.user.into();
+        new_state.public_ipv4 = vm.ip4.into();
+        new_state.public_ipv6 = vm.ip6.into();
+
+        Ok(Some(new_state))
+    }
+
+    async fn create(
+        &self,
+        planned_state: VmResourceState,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<VmResourceState> {
+        let provisioning = self
+            .create_vm_and_wait(&planned_state.config)
+            .await
+            .context("failed to create vm")?;
+
+        provisioning.push_diagnostics(diagnostics);
+        Ok(provisioning.into_state(planned_state))
+    }
+
+    async fn update(
+        &self,
+        prior_state: VmResourceState,
+        planned_state: VmResourceState,
+        _diagnostics: &mut Diagnostics,
+    ) -> Result<VmResourceState> {
+        // the ubicloud api can't change an existing vm, so every attribute that affects it
+        // requires replacement and an update only records the new name or timeouts
+        Ok(VmResourceState {
+            config: planned_state.config,
+            ..prior_state
+        })
+    }
+
+    async fn delete(
+        &self,
+        prior_state: VmResourceState,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<()> {
+        let deletion = self
+            .delete_vm_and_wait(&prior_state)
+            .await
+            .context("failed to delete vm")?;
+
+        if let Deletion::Interrupted = deletion {
+            push_diagnostic!(
+                diagnostics,
+                "provider stopped while deleting vm",
+                format!(
+                    "deletion of vm `{}` was requested but had not finished yet",
+                    prior_state.vm_name
+                ),
+                tf::diagnostic::Severity::Warning
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn import(&self, id: &str) -> Result<VmResourceState> {
+        let (project_id, location, vm_name) = match id.split('/').collect::<Vec<_>>()[..] {
+            [project_id, location, vm_name]
+                if !project_id.is_empty() && !location.is_empty() && !vm_name.is_empty() =>
+            {
+                (project_id, location, vm_name)
+            }
+            _ => {
+                return Err(anyhow!(
+                    "expected an id of the form `<project_id>/<location>/<vm_name>`, got `{id}`"
+                )
+                .context("invalid import id"));
+            }
+        };
+
+        let vm = self
+            .ubicloud
+            .get_vm(
+                project_id.to_string(),
+                location.to_string(),
+                vm_name.to_string(),
+            )
+            .await
+            .context("failed to get vm")?
+            .ok_or_else(|| {
+                anyhow!("vm `{vm_name}` does not exist in project `{project_id}` at `{location}`")
+                    .context("vm not found")
+            })?;
+
+        let name = friendly_name(&vm.name).to_string();
+
+        Ok(VmResourceState {
+            config: VmResourceConfig {
+                region: location.to_string().into(),
+                project_id: project_id.to_string().into(),
+                name: name.into(),
+                size: vm.size.into(),
+                // image and public key can't be read back, they are left empty and taken from
+                // the configuration on the next apply without replacing the vm
+                image: String::new().into(),
+                user: vm.user.into(),
+                public_key: String::new().into(),
+                enable_public_ipv4: Some(vm.ip4.is_some()),
+                timeouts: None,
+            },
+            vm_name: vm.name.into(),
+            public_ipv4: vm.ip4.into(),
+            public_ipv6: vm.ip6.into(),
+        })
+    }
+}
+
+/// The `ubicloud_vm` data source.
+pub struct VmDataSource {
+    ubicloud: Arc<UbicloudClient>,
+}
+
+impl VmDataSource {
+    pub fn new(ubicloud: Arc<UbicloudClient>) -> Self {
+        Self { ubicloud }
+    }
+}
+
+#[tonic::async_trait]
+impl DataSource for VmDataSource {
+    type Config = VmDataSourceConfig;
+    type State = VmDataSourceState;
+
+    async fn read(&self, config: VmDataSourceConfig) -> Result<VmDataSourceState> {
+        let vm = self
+            .ubicloud
+            .get_vm(
+                config.project_id.clone(),
+                config.region.clone(),
+                config.name.clone(),
+            )
+            .await
+            .context("failed to get vm")?;
+
+        let Some(vm) = vm else {
+            return Err(anyhow!(
+                "vm `{}` does not exist in project `{}` at `{}`",
+                config.name,
+                config.project_id,
+                config.region
+            )
+            .context("vm not found"));
+        };
+
+        Ok(VmDataSourceState {
+            config,
+            size: vm.size,
+            user: vm.user,
+            state: vm.state,
+            public_ipv4: vm.ip4,
+            public_ipv6: vm.ip6,
+        })
+    }
+}
+
+/// The `ubicloud_vms` data source.
+pub struct VmsDataSource {
+    ubicloud: Arc<UbicloudClient>,
+}
+
+impl VmsDataSource {
+    pub fn new(ubicloud: Arc<UbicloudClient>) -> Self {
+        Self { ubicloud }
+    }
+}
+
+#[tonic::async_trait]
+impl DataSource for VmsDataSource {
+    type Config = VmsDataSourceConfig;
+    type State = VmsDataSourceState;
+
+    async fn read(&self, config: VmsDataSourceConfig) -> Result<VmsDataSourceState> {
+        let vms = self
+            .ubicloud
+            .list_vm(config.project_id.clone(), config.region.clone())
+            .await
+            .context("failed to list vms")?;
+
+        let vms = vms
+            .into_iter()
+            .filter(|vm| match &config.name_prefix {
+                Some(prefix) => vm.name.starts_with(prefix.as_str()),
+                None => true,
+            })
+            .filter(|vm| match &config.state {
+                Some(state) => &vm.state == state,
+                None => true,
+            })
+            .map(|vm| VmsDataSourceItem {
+                name: vm.name,
+                region: vm.location,
+                size: vm.size,
+                user: vm.user,
+                state: vm.state,
+                public_ipv4: vm.ip4,
+                public_ipv6: vm.ip6,
+            })
+            .collect();
+
+        Ok(VmsDataSourceState { config, vms })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_terraform_provider::{
+        cty::{self, Value},
+        resource::AnyResource,
+        schema::Model,
+        util::{deserialize_dynamic_value, serialize_dynamic_value, IntoDynamicValue},
+    };
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path, path_regex},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::ubicloud::Credentials;
+
+    const VM_PATH: &str = "/project/pj1/location/hetzner-hel1/vm/web-a1b2c3";
+
+    /// A mock of the Ubicloud API that accepts any login.
+    async fn mock_api() -> MockServer {
+        let server = MockServer::start().await;
+
+        Mock::given(method("POST"))
+            .and(path("/login"))
+            .respond_with(ResponseTemplate::new(200).insert_header("authorization", "token"))
+            .mount(&server)
+            .await;
+
+        server
+    }
+
+    fn vm_resource(server: &MockServer) -> VmResource {
+        let ubicloud = UbicloudClient::with_base_url(
+            server.uri(),
+            Some(Credentials {
+                email: "user@example.com".to_string(),
+                password: "secret".to_string(),
+            }),
+        );
+
+        VmResource::new(Arc::new(ubicloud), CancellationToken::new())
+    }
+
+    fn vm_json(state: &str, size: &str, user: &str, ip4: Option<&str>) -> serde_json::Value {
+        json!({
+            "id": "vm1",
+            "name": "web-a1b2c3",
+            "state": state,
+            "location": "hetzner-hel1",
+            "display_size": size,
+            "unix_user": user,
+            "ip4": ip4,
+            "ip6": "2a01:4f9::2",
+        })
+    }
+
+    fn config() -> VmResourceConfig {
+        VmResourceConfig {
+            region: "hetzner-hel1".to_string().into(),
+            project_id: "pj1".to_string().into(),
+            name: "web".to_string().into(),
+            size: "standard-2".to_string().into(),
+            image: "ubuntu-jammy".to_string().into(),
+            user: "ubi".to_string().into(),
+            public_key: "ssh-ed25519 AAAA".to_string().into(),
+            enable_public_ipv4: Some(true),
+            timeouts: Some(VmTimeouts {
+                poll_interval: Some("1ms".to_string()),
+                max_poll_interval: Some("1ms".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn state() -> VmResourceState {
+        VmResourceState {
+            config: config(),
+            vm_name: "web-a1b2c3".to_string().into(),
+            public_ipv4: Some("10.0.0.1".to_string()).into(),
+            public_ipv6: Some("2a01:4f9::2".to_string()).into(),
+        }
+    }
+
+    async fn read(resource: &VmResource, state: &VmResourceState) -> tf::read_resource::Response {
+        let request = tf::read_resource::Request {
+            current_state: Some(serialize_dynamic_value(state).unwrap().into_dynamic_value()),
+            ..Default::default()
+        };
+
+        AnyResource::read(resource, request)
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    fn is_error(diagnostic: &tf::Diagnostic) -> bool {
+        diagnostic.severity == tf::diagnostic::Severity::Error as i32
+    }
+
+    #[tokio::test]
+    async fn read_drops_vms_that_no_longer_exist() {
+        let server = mock_api().await;
+        Mock::given(method("GET"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let response = read(&vm_resource(&server), &state()).await;
+
+        assert!(response.diagnostics.is_empty());
+        let new_state: Option<VmResourceState> =
+            deserialize_dynamic_value(response.new_state.unwrap()).unwrap();
+        assert_eq!(new_state, None);
+    }
+
+    #[tokio::test]
+    async fn read_refreshes_drifted_attributes() {
+        let server = mock_api().await;
+        Mock::given(method("GET"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vm_json(
+                "running",
+                "standard-4",
+                "admin",
+                None,
+            )))
+            .mount(&server)
+            .await;
+
+        let response = read(&vm_resource(&server), &state()).await;
+
+        assert!(response.diagnostics.is_empty());
+        let new_state: VmResourceState =
+            deserialize_dynamic_value(response.new_state.unwrap()).unwrap();
+        assert_eq!(
+            new_state,
+            VmResourceState {
+                config: VmResourceConfig {
+                    size: "standard-4".to_string().into(),
+                    user: "admin".to_string().into(),
+                    ..config()
+                },
+                public_ipv4: None.into(),
+                ..state()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn read_reports_api_errors() {
+        let server = mock_api().await;
+        Mock::given(method("GET"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let response = read(&vm_resource(&server), &state()).await;
+
+        assert!(response.new_state.is_none());
+        assert!(response.diagnostics.iter().any(is_error));
+    }
+
+    #[test]
+    fn strips_only_generated_suffixes_from_vm_names() {
+        assert_eq!(friendly_name("web-a1b2c3"), "web");
+        assert_eq!(friendly_name("my-web-0a1"), "my-web");
+        assert_eq!(friendly_name("my-bastion"), "my-bastion");
+        assert_eq!(friendly_name("db-primary1"), "db-primary1");
+        assert_eq!(friendly_name("bastion"), "bastion");
+        assert_eq!(friendly_name("-a1b2c3"), "-a1b2c3");
+
+        let generated = format!("web-{}", random_hex_suffix(VM_NAME_SUFFIX_BYTES));
+        assert_eq!(friendly_name(&generated), "web");
+    }
+
+    #[tokio::test]
+    async fn imports_hand_made_vm_names_whole() {
+        let mut vm = vm_json("running", "standard-2", "ubi", None);
+        vm["name"] = json!("my-bastion");
+
+        let server = mock_api().await;
+        Mock::given(method("GET"))
+            .and(path("/project/pj1/location/hetzner-hel1/vm/my-bastion"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vm))
+            .mount(&server)
+            .await;
+
+        let imported = Resource::import(&vm_resource(&server), "pj1/hetzner-hel1/my-bastion")
+            .await
+            .unwrap();
+
+        assert_eq!(imported.config.name, "my-bastion".to_string().into());
+        assert_eq!(imported.vm_name, "my-bastion".to_string().into());
+    }
+
+    #[tokio::test]
+    async fn plans_no_replacement_after_import() {
+        // the api may name the location differently than the id used to look the vm up
+        let mut vm = vm_json("running", "standard-2", "ubi", Some("10.0.0.1"));
+        vm["location"] = json!("eu-central-h1");
+
+        let server = mock_api().await;
+        Mock::given(method("GET"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vm))
+            .mount(&server)
+            .await;
+        let resource = vm_resource(&server);
+
+        let request = tf::import_resource_state::Request {
+            type_name: "ubicloud_vm".to_string(),
+            id: "pj1/hetzner-hel1/web-a1b2c3".to_string(),
+        };
+        let response = AnyResource::import(&resource, request)
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.diagnostics.is_empty());
+
+        let imported_state = response.imported_resources[0].state.clone().unwrap();
+        let imported: VmResourceState = deserialize_dynamic_value(imported_state.clone()).unwrap();
+        assert_eq!(imported.config.region, "hetzner-hel1".to_string().into());
+
+        let request = tf::plan_resource_change::Request {
+            prior_state: Some(imported_state),
+            config: Some(
+                serialize_dynamic_value(&config())
+                    .unwrap()
+                    .into_dynamic_value(),
+            ),
+            ..Default::default()
+        };
+        let response = AnyResource::plan(&resource, request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(response.diagnostics.is_empty());
+        assert!(response.requires_replace.is_empty());
+
+        let planned: VmResourceState =
+            deserialize_dynamic_value(response.planned_state.unwrap()).unwrap();
+        assert_eq!(planned, state());
+    }
+
+    #[tokio::test]
+    async fn delete_reports_api_errors_while_waiting() {
+        let server = mock_api().await;
+        Mock::given(method("DELETE"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(VM_PATH))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let result = vm_resource(&server).delete_vm_and_wait(&state()).await;
+
+        let Err(error) = result else {
+            panic!("expected the failed status check to be reported");
+        };
+        assert!(format!("{error:#}").contains("last seen state: unknown"));
+    }
+
+    #[tokio::test]
+    async fn apply_keeps_created_vms_whose_status_check_fails() {
+        let server = mock_api().await;
+        Mock::given(method("POST"))
+            .and(path("/project/pj1/location/hetzner-hel1/vm"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(vm_json(
+                "creating",
+                "standard-2",
+                "ubi",
+                None,
+            )))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/project/pj1/location/hetzner-hel1/vm/web-"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let resource = vm_resource(&server);
+
+        let config_value = serialize_dynamic_value(&config())
+            .unwrap()
+            .into_dynamic_value();
+        let request = tf::plan_resource_change::Request {
+            prior_state: Some(
+                serialize_dynamic_value(&None::<VmResourceState>)
+                    .unwrap()
+                    .into_dynamic_value(),
+            ),
+            proposed_new_state: Some(config_value.clone()),
+            config: Some(config_value.clone()),
+            ..Default::default()
+        };
+        let planned_state = AnyResource::plan(&resource, request)
+            .await
+            .unwrap()
+            .into_inner()
+            .planned_state;
+
+        let request = tf::apply_resource_change::Request {
+            prior_state: Some(
+                serialize_dynamic_value(&None::<VmResourceState>)
+                    .unwrap()
+                    .into_dynamic_value(),
+            ),
+            planned_state,
+            config: Some(config_value),
+            ..Default::default()
+        };
+        let response = AnyResource::apply(&resource, request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(response
+            .diagnostics
+            .iter()
+            .any(|diagnostic| is_error(diagnostic)
+                && diagnostic.summary == "vm did not become ready"));
+
+        // the vm name is generated by the provider, so take it from the status check
+        let requests = server.received_requests().await.unwrap();
+        let status_check = requests
+            .iter()
+            .find(|request| request.method == wiremock::http::Method::Get)
+            .unwrap();
+        let vm_name = status_check.url.path().rsplit('/').next().unwrap();
+
+        let new_state: VmResourceState =
+            deserialize_dynamic_value(response.new_state.unwrap()).unwrap();
+        assert_eq!(new_state.vm_name, vm_name.to_string().into());
+        assert_eq!(new_state.config, config());
+    }
+
+    /// Asserts that the serde fields of `value` are exactly the attributes and blocks of
+    /// `ty`, so a `#[serde(rename)]` or a `#[schema(flatten)]` without `#[serde(flatten)]`
+    /// can't make the state drift from the schema.
+    fn assert_fields_match(value: &Value, ty: &Type, at: &str) {
+        match (value, ty) {
+            (Value::Object(fields), Type::Object(attributes)) => {
+                let mut names: Vec<&str> =
+                    attributes.iter().map(|(name, _)| name.as_str()).collect();
+                names.sort_unstable();
+                names.dedup();
+                assert_eq!(
+                    names.len(),
+                    attributes.len(),
+                    "duplicate attribute in `{at}`"
+                );
+
+                let keys: Vec<&str> = fields.keys().map(String::as_str).collect();
+                assert_eq!(keys, names, "fields of `{at}` don't match the schema");
+
+                for (name, ty) in attributes {
+                    assert_fields_match(&fields[name], ty, &format!("{at}.{name}"));
+                }
+            }
+            (Value::List(elements), Type::List(element)) => {
+                for value in elements {
+                    assert_fields_match(value, element, at);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn assert_matches_schema<T: Model + Serialize>(sample: &T) {
+        let ty = T::block().cty_type();
+        let value = cty::to_value(sample).unwrap();
+
+        assert_fields_match(&value, &ty, std::any::type_name::<T>());
+        value.conform(&ty).unwrap();
+    }
+
+    #[test]
+    fn resource_state_matches_the_schema() {
+        assert_matches_schema(&state());
+    }
+
+    #[test]
+    fn data_source_states_match_the_schema() {
+        assert_matches_schema(&VmDataSourceState {
+            config: VmDataSourceConfig {
+                region: "hetzner-hel1".to_string(),
+                project_id: "pj1".to_string(),
+                name: "web-a1b2c3".to_string(),
+            },
+            size: "standard-2".to_string(),
+            user: "ubi".to_string(),
+            state: VmState::Running,
+            public_ipv4: Some("10.0.0.1".to_string()),
+            public_ipv6: Some("2a01:4f9::2".to_string()),
+        });
+
+        assert_matches_schema(&VmsDataSourceState {
+            config: VmsDataSourceConfig {
+                region: "hetzner-hel1".to_string(),
+                project_id: "pj1".to_string(),
+                name_prefix: Some("web-".to_string()),
+                state: Some(VmState::Running),
+            },
+            vms: vec![VmsDataSourceItem {
+                name: "web-a1b2c3".to_string(),
+                region: "hetzner-hel1".to_string(),
+                size: "standard-2".to_string(),
+                user: "ubi".to_string(),
+                state: VmState::Running,
+                public_ipv4: None,
+                public_ipv6: Some("2a01:4f9::2".to_string()),
+            }],
+        });
+    }
+
+    #[tokio::test]
+    async fn plans_configs_with_unknown_values() {
+        let mut config = cty::to_value(&config()).unwrap();
+        let Value::Object(attributes) = &mut config else {
+            panic!("expected an object, got {config:?}");
+        };
+        attributes.insert("name".to_string(), Value::unknown());
+        attributes.insert("enable_public_ipv4".to_string(), Value::unknown());
+
+        let server = mock_api().await;
+        let request = tf::plan_resource_change::Request {
+            prior_state: Some(
+                serialize_dynamic_value(&None::<VmResourceState>)
+                    .unwrap()
+                    .into_dynamic_value(),
+            ),
+            config: Some(config.to_msgpack().into_dynamic_value()),
+            ..Default::default()
+        };
+        let response = AnyResource::plan(&vm_resource(&server), request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(response.diagnostics.is_empty());
+
+        let planned = Value::from_msgpack(
+            &response.planned_state.unwrap().msgpack,
+            &VmResourceState::block().cty_type(),
+        )
+        .unwrap();
+        let Value::Object(attributes) = planned else {
+            panic!("expected an object, got {planned:?}");
+        };
+        assert_eq!(attributes["name"], Value::unknown());
+        assert_eq!(attributes["enable_public_ipv4"], Value::unknown());
+        // nothing is known about the real name but that there will be one
+        assert_eq!(
+            attributes["vm_name"],
+            Value::Unknown(Refinements::default().not_null())
+        );
+        assert_eq!(attributes["size"], Value::String("standard-2".to_string()));
+    }
+}
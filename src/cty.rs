@@ -1,96 +1,811 @@
-use anyhow::{anyhow, Result};
-use rmp::{
-    decode::{self, Bytes, RmpRead},
-    encode, Marker,
-};
+//! Terraform's type system, cty: types, values and their msgpack and JSON encodings.
+//!
+//! Values travel between Terraform and the provider as [`Value`]s, which are converted from
+//! and to Rust types with [`from_value`] and [`to_value`]. Attributes that may not be known
+//! while planning are declared as [`MaybeUnknown`].
 
-use crate::util::UNKNOWN_STRING;
+use std::{collections::BTreeMap, fmt};
 
-fn peek_marker(bytes: &mut Bytes) -> Result<Marker> {
-    let mut bytes = bytes.clone();
+use anyhow::{anyhow, bail, Context};
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Number;
 
-    let marker = decode::read_marker(&mut bytes).map_err(|_| anyhow!("expected marker"))?;
-    Ok(marker)
+mod de;
+mod msgpack;
+mod ser;
+
+pub use de::from_value;
+pub use ser::to_value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    String,
+    Number,
+    Bool,
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>),
+    Object(Vec<(String, Type)>),
+    Tuple(Vec<Type>),
 }
 
-fn peek_str_len(bytes: &mut Bytes) -> Result<usize> {
-    let mut bytes = bytes.clone();
+impl Type {
+    pub fn list(element: Type) -> Self {
+        Type::List(Box::new(element))
+    }
+
+    pub fn set(element: Type) -> Self {
+        Type::Set(Box::new(element))
+    }
+
+    pub fn map(element: Type) -> Self {
+        Type::Map(Box::new(element))
+    }
+
+    pub fn object<S: Into<String>>(attributes: impl IntoIterator<Item = (S, Type)>) -> Self {
+        Type::Object(
+            attributes
+                .into_iter()
+                .map(|(name, ty)| (name.into(), ty))
+                .collect(),
+        )
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Type::String => "string".into(),
+            Type::Number => "number".into(),
+            Type::Bool => "bool".into(),
+            Type::List(element) => serde_json::json!(["list", element.to_json()]),
+            Type::Set(element) => serde_json::json!(["set", element.to_json()]),
+            Type::Map(element) => serde_json::json!(["map", element.to_json()]),
+            Type::Object(attributes) => {
+                let attributes: serde_json::Map<String, serde_json::Value> = attributes
+                    .iter()
+                    .map(|(name, ty)| (name.clone(), ty.to_json()))
+                    .collect();
+
+                serde_json::json!(["object", attributes])
+            }
+            Type::Tuple(elements) => {
+                let elements: Vec<serde_json::Value> = elements.iter().map(Type::to_json).collect();
 
-    let len = decode::read_str_len(&mut bytes).map_err(|_| anyhow!("expected string"))?;
-    Ok(len as usize)
+                serde_json::json!(["tuple", elements])
+            }
+        }
+    }
+
+    /// JSON encoding of the type, as expected in `tf::schema::Attribute::type`.
+    pub fn encode(&self) -> Vec<u8> {
+        self.to_json().to_string().into_bytes()
+    }
 }
 
-pub fn encode_unknown_string_values(bytes: Vec<u8>) -> Result<Vec<u8>> {
-    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+/// A cty value. Object attributes and map elements are kept sorted by name, the order
+/// Terraform encodes them in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
 
-    let mut work_bytes = Bytes::from(bytes.as_slice());
+    /// A value that is not known yet, like a computed attribute while planning, with what
+    /// is already known about it.
+    Unknown(Refinements),
 
-    loop {
-        if work_bytes.remaining_slice().len() == 0 {
-            break;
+    Bool(bool),
+    Number(Number),
+    String(String),
+    List(Vec<Value>),
+    Set(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Object(BTreeMap<String, Value>),
+    Tuple(Vec<Value>),
+}
+
+impl Value {
+    /// An unknown value nothing is known about.
+    pub fn unknown() -> Self {
+        Value::Unknown(Refinements::default())
+    }
+
+    /// Decodes a msgpack encoded value of type `ty`.
+    pub fn from_msgpack(bytes: &[u8], ty: &Type) -> anyhow::Result<Self> {
+        msgpack::decode(bytes, Some(ty))
+    }
+
+    /// Decodes a msgpack encoded value without knowing its type. Arrays become tuples and
+    /// maps become objects, which is all serde needs to tell them apart.
+    pub fn from_msgpack_implied(bytes: &[u8]) -> anyhow::Result<Self> {
+        msgpack::decode(bytes, None)
+    }
+
+    /// Decodes a JSON encoded value without knowing its type, like
+    /// [`Value::from_msgpack_implied`]. JSON has no way to encode unknown values, so there are
+    /// none in the result.
+    pub fn from_json_implied(bytes: &[u8]) -> anyhow::Result<Self> {
+        let value: serde_json::Value = serde_json::from_slice(bytes)?;
+        Ok(value.into())
+    }
+
+    pub fn to_msgpack(&self) -> Vec<u8> {
+        msgpack::encode(self)
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Value::Unknown(_))
+    }
+
+    /// Whether the value or anything nested in it is not known yet.
+    pub fn contains_unknown(&self) -> bool {
+        match self {
+            Value::Unknown(_) => true,
+            Value::List(elements) | Value::Set(elements) | Value::Tuple(elements) => {
+                elements.iter().any(Value::contains_unknown)
+            }
+            Value::Map(elements) | Value::Object(elements) => {
+                elements.values().any(Value::contains_unknown)
+            }
+            _ => false,
         }
+    }
 
-        let Ok(marker) = peek_marker(&mut work_bytes) else {
-            result.push(work_bytes.read_u8()?);
-            continue;
-        };
+    /// Brings the value into the exact shape of `ty`, which is what Terraform expects of
+    /// values a provider returns: every object attribute is present, null if it has no value,
+    /// and sequences and maps are the kind of collection the type declares. Values of the
+    /// wrong type and attributes the type doesn't have are errors.
+    pub fn conform(self, ty: &Type) -> anyhow::Result<Self> {
+        Ok(match (self, ty) {
+            (value @ (Value::Null | Value::Unknown(_)), _) => value,
+            (value @ Value::Bool(_), Type::Bool) => value,
+            (value @ Value::Number(_), Type::Number) => value,
+            (value @ Value::String(_), Type::String) => value,
+            (
+                Value::List(elements) | Value::Set(elements) | Value::Tuple(elements),
+                Type::List(element) | Type::Set(element),
+            ) => {
+                let elements = conform_elements(elements, |_| Some(element))?;
 
-        match marker {
-            Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32 => {
-                let mut buf = vec![0u8; peek_str_len(&mut work_bytes)?];
-                let str: &str = decode::read_str(&mut work_bytes, &mut buf)
-                    .map_err(|_| anyhow!("expected string"))?;
-
-                if str == UNKNOWN_STRING {
-                    result.push(Marker::FixExt1.to_u8());
-                    result.push(0x00);
-                    result.push(0x00);
-                } else {
-                    let mut data = Vec::new();
-                    encode::write_str(&mut data, str).map_err(|_| anyhow!("expected string"))?;
-                    result.extend(data);
+                match ty {
+                    Type::Set(_) => Value::Set(elements),
+                    _ => Value::List(elements),
                 }
             }
-            _ => {
-                result.push(work_bytes.read_u8()?);
+            (
+                Value::List(elements) | Value::Set(elements) | Value::Tuple(elements),
+                Type::Tuple(types),
+            ) => {
+                if elements.len() != types.len() {
+                    bail!(
+                        "expected a tuple of {} elements, got {}",
+                        types.len(),
+                        elements.len()
+                    );
+                }
+
+                Value::Tuple(conform_elements(elements, |index| types.get(index))?)
             }
-        };
+            (Value::Map(elements) | Value::Object(elements), Type::Map(element)) => Value::Map(
+                elements
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let value = value
+                            .conform(element)
+                            .with_context(|| format!("in `{name}`"))?;
+                        Ok((name, value))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            (Value::Map(mut elements) | Value::Object(mut elements), Type::Object(attributes)) => {
+                let mut conformed = BTreeMap::new();
+
+                for (name, ty) in attributes {
+                    let value = elements
+                        .remove(name)
+                        .unwrap_or(Value::Null)
+                        .conform(ty)
+                        .with_context(|| format!("in `{name}`"))?;
+                    conformed.insert(name.clone(), value);
+                }
+
+                if let Some(name) = elements.keys().next() {
+                    bail!("unexpected attribute `{name}`");
+                }
+
+                Value::Object(conformed)
+            }
+            (value, ty) => bail!("expected a value of type {ty}, got {}", value.kind()),
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Unknown(_) => "unknown value",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::List(_) | Value::Set(_) | Value::Tuple(_) => "sequence",
+            Value::Map(_) | Value::Object(_) => "map",
+        }
+    }
+
+    /// Marks everything that is unknown in `other` as unknown in `self` as well, matching
+    /// object attributes and map elements by name and sequence elements by position.
+    ///
+    /// Used to carry values that are not known in the configuration over into a planned
+    /// state, which a model can only hold as a placeholder.
+    pub fn mark_unknowns_from(&mut self, other: &Value) {
+        match (self, other) {
+            (this, other @ Value::Unknown(_)) => *this = other.clone(),
+            (
+                Value::List(elements) | Value::Set(elements) | Value::Tuple(elements),
+                Value::List(others) | Value::Set(others) | Value::Tuple(others),
+            ) => {
+                for (element, other) in elements.iter_mut().zip(others) {
+                    element.mark_unknowns_from(other);
+                }
+            }
+            (
+                Value::Map(elements) | Value::Object(elements),
+                Value::Map(others) | Value::Object(others),
+            ) => {
+                for (name, element) in elements.iter_mut() {
+                    if let Some(other) = others.get(name) {
+                        element.mark_unknowns_from(other);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn conform_elements<'a>(
+    elements: Vec<Value>,
+    ty: impl Fn(usize) -> Option<&'a Type>,
+) -> anyhow::Result<Vec<Value>> {
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(index, element)| {
+            let ty = ty(index).ok_or_else(|| anyhow!("unexpected element {index}"))?;
+            element
+                .conform(ty)
+                .with_context(|| format!("in element {index}"))
+        })
+        .collect()
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Bool(value),
+            serde_json::Value::Number(value) => Value::Number(value),
+            serde_json::Value::String(value) => Value::String(value),
+            serde_json::Value::Array(elements) => {
+                Value::Tuple(elements.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(attributes) => Value::Object(
+                attributes
+                    .into_iter()
+                    .map(|(name, value)| (name, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// What is already known about an unknown value, which lets Terraform check conditions and
+/// show better plans before the value itself is known.
+///
+/// Refinements have to hold for the final value: Terraform rejects an apply that produces a
+/// value outside of what was planned.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Refinements {
+    /// The value is definitely not null.
+    pub not_null: bool,
+
+    /// Strings only: the value starts with this prefix.
+    pub string_prefix: Option<String>,
+
+    /// Numbers only: the value is at least, or above, this bound.
+    pub number_lower_bound: Option<NumberBound>,
+
+    /// Numbers only: the value is at most, or below, this bound.
+    pub number_upper_bound: Option<NumberBound>,
+
+    /// Collections only: the least number of elements the value has.
+    pub length_lower_bound: Option<u64>,
+
+    /// Collections only: the most elements the value has.
+    pub length_upper_bound: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NumberBound {
+    pub value: Number,
+    pub inclusive: bool,
+}
+
+impl Refinements {
+    pub fn not_null(mut self) -> Self {
+        self.not_null = true;
+        self
+    }
+
+    pub fn string_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.string_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn number_lower_bound(mut self, value: impl Into<Number>, inclusive: bool) -> Self {
+        self.number_lower_bound = Some(NumberBound {
+            value: value.into(),
+            inclusive,
+        });
+        self
+    }
+
+    pub fn number_upper_bound(mut self, value: impl Into<Number>, inclusive: bool) -> Self {
+        self.number_upper_bound = Some(NumberBound {
+            value: value.into(),
+            inclusive,
+        });
+        self
+    }
+
+    pub fn length_lower_bound(mut self, length: u64) -> Self {
+        self.length_lower_bound = Some(length);
+        self
+    }
+
+    pub fn length_upper_bound(mut self, length: u64) -> Self {
+        self.length_upper_bound = Some(length);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Refinements::default()
     }
+}
+
+/// Name of the struct [`MaybeUnknown::Unknown`] and [`MaybeUnknown::Refined`] serialize
+/// as. The value serializer turns it into [`Value::Unknown`], other formats see a unit or
+/// the refinements.
+const UNKNOWN_TOKEN: &str = "$cty::unknown";
 
-    Ok(result)
+/// An attribute that may not be known yet, like a computed attribute in a planned state.
+///
+/// Models that use plain types see optional values that are not known yet as `None`, and
+/// can't hold unknown values of other types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaybeUnknown<T> {
+    Known(T),
+    Unknown,
+
+    /// Not known yet, but with some facts about the eventual value.
+    Refined(Refinements),
 }
 
-pub fn decode_unknown_string_values(bytes: Vec<u8>) -> Result<Vec<u8>> {
-    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+impl<T> MaybeUnknown<T> {
+    pub fn known(&self) -> Option<&T> {
+        match self {
+            MaybeUnknown::Known(value) => Some(value),
+            MaybeUnknown::Unknown | MaybeUnknown::Refined(_) => None,
+        }
+    }
+
+    pub fn into_known(self) -> Option<T> {
+        match self {
+            MaybeUnknown::Known(value) => Some(value),
+            MaybeUnknown::Unknown | MaybeUnknown::Refined(_) => None,
+        }
+    }
 
-    let mut work_bytes = Bytes::from(bytes.as_slice());
+    pub fn is_known(&self) -> bool {
+        matches!(self, MaybeUnknown::Known(_))
+    }
 
-    loop {
-        if work_bytes.remaining_slice().len() == 0 {
-            break;
+    /// The value, or an error naming `attribute` if it is not known yet. For hooks that run
+    /// once Terraform knows the whole configuration, like creating a resource.
+    pub fn require(&self, attribute: &str) -> anyhow::Result<&T> {
+        self.known()
+            .ok_or_else(|| anyhow!("`{attribute}` is not known yet"))
+    }
+}
+
+impl<T> From<T> for MaybeUnknown<T> {
+    fn from(value: T) -> Self {
+        MaybeUnknown::Known(value)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for MaybeUnknown<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaybeUnknown::Known(value) => value.fmt(f),
+            MaybeUnknown::Unknown | MaybeUnknown::Refined(_) => f.write_str("(known after apply)"),
         }
+    }
+}
 
-        let Ok(marker) = peek_marker(&mut work_bytes) else {
-            result.push(work_bytes.read_u8()?);
-            continue;
-        };
+impl<T: Serialize> Serialize for MaybeUnknown<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MaybeUnknown::Known(value) => value.serialize(serializer),
+            MaybeUnknown::Unknown => serializer.serialize_unit_struct(UNKNOWN_TOKEN),
+            MaybeUnknown::Refined(refinements) => {
+                serializer.serialize_newtype_struct(UNKNOWN_TOKEN, refinements)
+            }
+        }
+    }
+}
 
-        match marker {
-            Marker::FixExt1 => {
-                let _ = work_bytes.read_u8()?;
-                let _ = work_bytes.read_u8()?;
-                let _ = work_bytes.read_u8()?;
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for MaybeUnknown<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MaybeUnknownVisitor<T>(std::marker::PhantomData<T>);
 
-                let mut data = Vec::new();
-                encode::write_str(&mut data, UNKNOWN_STRING)
-                    .map_err(|_| anyhow!("expected string"))?;
-                result.extend(data);
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for MaybeUnknownVisitor<T> {
+            type Value = MaybeUnknown<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a value that may not be known yet")
+            }
+
+            // only sent by the value deserializer, for unknown values
+            fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+                Ok(MaybeUnknown::Unknown)
             }
-            _ => {
-                result.push(work_bytes.read_u8()?);
+
+            // only sent by the value deserializer, for refined unknown values
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                Refinements::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(MaybeUnknown::Refined)
             }
+
+            // buffered values, like those of `#[serde(flatten)]`-ed structs, get here even
+            // when they are unknown, so the value is looked at before `T` deserializes it
+            fn visit_newtype_struct<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                match Value::deserialize(deserializer)? {
+                    Value::Unknown(refinements) if refinements.is_empty() => {
+                        Ok(MaybeUnknown::Unknown)
+                    }
+                    Value::Unknown(refinements) => Ok(MaybeUnknown::Refined(refinements)),
+                    value => T::deserialize(value)
+                        .map(MaybeUnknown::Known)
+                        .map_err(serde::de::Error::custom),
+                }
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(
+            UNKNOWN_TOKEN,
+            MaybeUnknownVisitor(std::marker::PhantomData),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Planned {
+        name: String,
+        id: MaybeUnknown<String>,
+        size: MaybeUnknown<i64>,
+        tags: MaybeUnknown<Vec<String>>,
+        address: MaybeUnknown<Option<String>>,
+    }
+
+    fn object<const N: usize>(attributes: [(&str, Value); N]) -> Value {
+        Value::Object(
+            attributes
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn round_trips_unknowns_of_any_type_through_models() {
+        let planned = Planned {
+            name: "vm".to_string(),
+            id: MaybeUnknown::Unknown,
+            size: MaybeUnknown::Unknown,
+            tags: MaybeUnknown::Unknown,
+            address: MaybeUnknown::Known(None),
         };
+
+        let value = to_value(&planned).unwrap();
+        assert_eq!(
+            value,
+            object([
+                ("name", Value::String("vm".to_string())),
+                ("id", Value::unknown()),
+                ("size", Value::unknown()),
+                ("tags", Value::unknown()),
+                ("address", Value::Null),
+            ])
+        );
+
+        let bytes = value.to_msgpack();
+        let decoded = Value::from_msgpack_implied(&bytes).unwrap();
+        assert_eq!(from_value::<Planned>(decoded).unwrap(), planned);
     }
 
-    Ok(result)
+    #[test]
+    fn round_trips_refined_unknowns_through_models() {
+        let refinements = Refinements::default().not_null().string_prefix("vm-");
+        let planned = Planned {
+            name: "vm".to_string(),
+            id: MaybeUnknown::Refined(refinements.clone()),
+            size: MaybeUnknown::Refined(Refinements::default().number_lower_bound(1, true)),
+            tags: MaybeUnknown::Unknown,
+            address: MaybeUnknown::Known(None),
+        };
+
+        let value = to_value(&planned).unwrap();
+        let Value::Object(attributes) = &value else {
+            panic!("expected an object, got {value:?}");
+        };
+        assert_eq!(attributes["id"], Value::Unknown(refinements));
+        assert_eq!(attributes["tags"], Value::unknown());
+
+        let bytes = value.to_msgpack();
+        let decoded = Value::from_msgpack_implied(&bytes).unwrap();
+        assert_eq!(from_value::<Planned>(decoded).unwrap(), planned);
+    }
+
+    #[test]
+    fn keeps_strings_that_look_like_unknown_values() {
+        let planned = Planned {
+            name: "<unknown>".to_string(),
+            id: MaybeUnknown::Known("<unknown>".to_string()),
+            size: MaybeUnknown::Known(2),
+            tags: MaybeUnknown::Known(vec!["a".to_string()]),
+            address: MaybeUnknown::Known(Some("10.0.0.1".to_string())),
+        };
+
+        let value = to_value(&planned).unwrap();
+        assert!(!value.contains_unknown());
+        assert_eq!(from_value::<Planned>(value).unwrap(), planned);
+    }
+
+    #[test]
+    fn rejects_unknown_values_in_plain_fields() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Config {
+            name: String,
+        }
+
+        let err = from_value::<Config>(object([("name", Value::unknown())])).unwrap_err();
+        assert!(err.to_string().contains("not known yet"), "{err}");
+    }
+
+    #[test]
+    fn reads_unknown_optional_values_in_plain_models_as_none() {
+        #[derive(Deserialize)]
+        struct Config {
+            public: Option<bool>,
+            size: Option<i64>,
+            tags: Option<Vec<String>>,
+        }
+
+        let config: Config = from_value(object([
+            ("public", Value::unknown()),
+            ("size", Value::unknown()),
+            ("tags", Value::unknown()),
+        ]))
+        .unwrap();
+        assert_eq!(config.public, None);
+        assert_eq!(config.size, None);
+        assert_eq!(config.tags, None);
+    }
+
+    #[test]
+    fn reads_unknown_values_in_flattened_models() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Config {
+            name: MaybeUnknown<String>,
+            address: MaybeUnknown<Option<String>>,
+            public: Option<bool>,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct State {
+            #[serde(flatten)]
+            config: Config,
+            id: MaybeUnknown<String>,
+        }
+
+        let value = object([
+            ("name", Value::unknown()),
+            ("address", Value::unknown()),
+            ("public", Value::unknown()),
+            ("id", Value::unknown()),
+        ]);
+        assert_eq!(
+            from_value::<State>(value).unwrap(),
+            State {
+                config: Config {
+                    name: MaybeUnknown::Unknown,
+                    address: MaybeUnknown::Unknown,
+                    public: None,
+                },
+                id: MaybeUnknown::Unknown,
+            }
+        );
+
+        // null stays apart from unknown
+        let value = object([
+            ("name", Value::String("vm".to_string())),
+            ("address", Value::Null),
+            ("public", Value::Bool(true)),
+            ("id", Value::String("vm-1".to_string())),
+        ]);
+        assert_eq!(
+            from_value::<State>(value).unwrap(),
+            State {
+                config: Config {
+                    name: MaybeUnknown::Known("vm".to_string()),
+                    address: MaybeUnknown::Known(None),
+                    public: Some(true),
+                },
+                id: MaybeUnknown::Known("vm-1".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn reads_flattened_models() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            name: String,
+            public: Option<bool>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct State {
+            #[serde(flatten)]
+            config: Config,
+            id: MaybeUnknown<String>,
+        }
+
+        let value = object([
+            ("name", Value::String("vm".to_string())),
+            ("public", Value::Null),
+            ("id", Value::unknown()),
+        ]);
+
+        let state: State = from_value(value.clone()).unwrap();
+        assert_eq!(
+            state,
+            State {
+                config: Config {
+                    name: "vm".to_string(),
+                    public: None,
+                },
+                id: MaybeUnknown::Unknown,
+            }
+        );
+        assert_eq!(to_value(&state).unwrap(), value);
+    }
+
+    #[test]
+    fn conforms_values_to_their_type() {
+        let ty = Type::object([
+            ("name", Type::String),
+            ("public", Type::Bool),
+            ("tags", Type::set(Type::String)),
+            ("labels", Type::map(Type::String)),
+            ("id", Type::String),
+        ]);
+
+        let value = object([
+            ("name", Value::String("vm".to_string())),
+            ("tags", Value::List(vec![Value::String("a".to_string())])),
+            (
+                "labels",
+                object([("env", Value::String("prod".to_string()))]),
+            ),
+            ("id", Value::unknown()),
+        ]);
+
+        assert_eq!(
+            value.conform(&ty).unwrap(),
+            object([
+                ("name", Value::String("vm".to_string())),
+                ("public", Value::Null),
+                ("tags", Value::Set(vec![Value::String("a".to_string())])),
+                (
+                    "labels",
+                    Value::Map(BTreeMap::from([(
+                        "env".to_string(),
+                        Value::String("prod".to_string())
+                    )]))
+                ),
+                ("id", Value::unknown()),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_values_that_dont_conform() {
+        let ty = Type::object([(
+            "network",
+            Type::object([("ports", Type::list(Type::Number))]),
+        )]);
+
+        let value = object([(
+            "network",
+            object([(
+                "ports",
+                Value::List(vec![
+                    Value::Number(22.into()),
+                    Value::String("ssh".to_string()),
+                ]),
+            )]),
+        )]);
+        let err = value.conform(&ty).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "in `network`: in `ports`: in element 1: expected a value of type \"number\", got string"
+        );
+
+        let value = object([("other", Value::Bool(true))]);
+        let err = value.conform(&ty).unwrap_err();
+        assert_eq!(format!("{err:#}"), "unexpected attribute `other`");
+
+        let value = Value::Tuple(vec![Value::Bool(true)]);
+        assert!(value
+            .conform(&Type::Tuple(vec![Type::Bool, Type::Bool]))
+            .is_err());
+    }
+
+    #[test]
+    fn marks_unknowns_from_config() {
+        let config = object([
+            ("name", Value::unknown()),
+            ("tags", Value::List(vec![Value::unknown()])),
+        ]);
+
+        let mut planned = object([
+            ("name", Value::String("<unknown>".to_string())),
+            (
+                "tags",
+                Value::List(vec![Value::String("<unknown>".to_string())]),
+            ),
+            ("id", Value::unknown()),
+            ("size", Value::Number(2.into())),
+        ]);
+        planned.mark_unknowns_from(&config);
+
+        assert_eq!(
+            planned,
+            object([
+                ("name", Value::unknown()),
+                ("tags", Value::List(vec![Value::unknown()])),
+                ("id", Value::unknown()),
+                ("size", Value::Number(2.into())),
+            ])
+        );
+    }
 }
@@ -0,0 +1,369 @@
+//! Converts cty values to Rust values with serde.
+
+use std::{collections::btree_map, fmt, vec};
+
+use serde::{
+    de::{self, DeserializeOwned, IntoDeserializer, Visitor},
+    forward_to_deserialize_any,
+};
+
+use serde_json::Number;
+
+use super::{Value, UNKNOWN_TOKEN};
+
+/// Converts a cty value to `T`.
+///
+/// Unknown values become [`MaybeUnknown::Unknown`](super::MaybeUnknown), or
+/// [`MaybeUnknown::Refined`](super::MaybeUnknown) if anything is known about them, or `None`
+/// where an `Option` is expected. `None` loses the unknown, callers that hand values back to
+/// Terraform restore it with [`Value::mark_unknowns_from`]. Unknown values anywhere else are
+/// an error.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> anyhow::Result<T> {
+    Ok(T::deserialize(value)?)
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Deserializes like `deserialize_any`, but fails for unknown values, which only `Option`
+/// and [`MaybeUnknown`](super::MaybeUnknown) can hold.
+macro_rules! deserialize_known {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                de::Deserializer::deserialize_any(self.require_known()?, visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            // only reached without a type to expect, like for values buffered for
+            // `#[serde(flatten)]`. `visit_none` keeps them apart from null, so `Option` still
+            // reads them as `None` and `MaybeUnknown` as unknown, without refinements
+            Value::Unknown(_) => visitor.visit_none(),
+            Value::Bool(value) => visitor.visit_bool(value),
+            Value::Number(value) => {
+                if let Some(value) = value.as_u64() {
+                    visitor.visit_u64(value)
+                } else if let Some(value) = value.as_i64() {
+                    visitor.visit_i64(value)
+                } else {
+                    visitor.visit_f64(value.as_f64().unwrap_or_default())
+                }
+            }
+            Value::String(value) => visitor.visit_string(value),
+            Value::List(elements) | Value::Set(elements) | Value::Tuple(elements) => {
+                visitor.visit_seq(SeqAccess(elements.into_iter()))
+            }
+            Value::Map(elements) | Value::Object(elements) => visitor.visit_map(MapAccess {
+                elements: elements.into_iter(),
+                next_value: None,
+            }),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Null | Value::Unknown(_) => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match (name, self) {
+            (UNKNOWN_TOKEN, Value::Unknown(refinements)) if refinements.is_empty() => {
+                visitor.visit_unit()
+            }
+            (UNKNOWN_TOKEN, Value::Unknown(refinements)) => {
+                let refinements = super::to_value(&refinements).map_err(de::Error::custom)?;
+                de::Deserializer::deserialize_any(refinements, visitor)
+            }
+            (_, value) => visitor.visit_newtype_struct(value),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Map(elements) | Value::Object(elements) if elements.len() == 1 => {
+                let (variant, value) = elements.into_iter().next().expect("one element");
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            _ => Err(de::Error::custom(
+                "expected a string or an object with a single attribute for an enum",
+            )),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.require_known()?, visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.require_known()?, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.require_known()?, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.require_known()?, visitor)
+    }
+
+    deserialize_known! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+        deserialize_seq deserialize_map
+    }
+
+    forward_to_deserialize_any! {
+        identifier ignored_any
+    }
+}
+
+impl Value {
+    fn require_known(self) -> Result<Self, Error> {
+        match self {
+            Value::Unknown(_) => Err(de::Error::custom(
+                "value is not known yet, only optional and `MaybeUnknown` fields can hold one",
+            )),
+            value => Ok(value),
+        }
+    }
+}
+
+/// Captures values of any self-describing format, which lets
+/// [`MaybeUnknown`](super::MaybeUnknown) tell unknown values apart before deserializing
+/// the known ones. Unknown values arrive as `None`, see [`Value`]'s `deserialize_any`.
+impl<'de> de::Deserialize<'de> for Value {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a cty value")
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+                Ok(Value::Bool(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+                Ok(Value::Number(value.into()))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+                Ok(Value::Number(value.into()))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+                Number::from_f64(value)
+                    .map(Value::Number)
+                    .ok_or_else(|| de::Error::custom("numbers must be finite"))
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+                Ok(Value::String(value.to_string()))
+            }
+
+            fn visit_string<E: de::Error>(self, value: String) -> Result<Value, E> {
+                Ok(Value::String(value))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+                Ok(Value::Null)
+            }
+
+            fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+                Ok(Value::unknown())
+            }
+
+            fn visit_some<D: de::Deserializer<'de>>(self, value: D) -> Result<Value, D::Error> {
+                de::Deserialize::deserialize(value)
+            }
+
+            fn visit_newtype_struct<D: de::Deserializer<'de>>(
+                self,
+                value: D,
+            ) -> Result<Value, D::Error> {
+                de::Deserialize::deserialize(value)
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+                let mut elements = vec![];
+                while let Some(element) = seq.next_element()? {
+                    elements.push(element);
+                }
+
+                Ok(Value::List(elements))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+                let mut elements = std::collections::BTreeMap::new();
+                while let Some((name, value)) = map.next_entry()? {
+                    elements.insert(name, value);
+                }
+
+                Ok(Value::Object(elements))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct SeqAccess(vec::IntoIter<Value>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        self.0
+            .next()
+            .map(|element| seed.deserialize(element))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapAccess {
+    elements: btree_map::IntoIter<String, Value>,
+    next_value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let Some((name, value)) = self.elements.next() else {
+            return Ok(None);
+        };
+
+        self.next_value = Some(value);
+        seed.deserialize(Value::String(name)).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        let value = self
+            .next_value
+            .take()
+            .ok_or_else(|| de::Error::custom("map value without a key"))?;
+
+        seed.deserialize(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Value), Error> {
+        let variant = seed.deserialize(Value::String(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self, visitor)
+    }
+}
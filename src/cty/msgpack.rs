@@ -0,0 +1,811 @@
+//! The msgpack encoding of cty values, as used in `tf::DynamicValue::msgpack`.
+//!
+//! Unknown values are encoded as an extension value of type 0, in any position. Unknown
+//! values with [`Refinements`] use extension type 12 instead, with a map of refinements as
+//! the payload.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rmp::{encode, Marker};
+use serde_json::Number;
+
+use super::{NumberBound, Refinements, Type, Value};
+
+/// Extension type Terraform marks unknown values with.
+const UNKNOWN_EXT_TYPE: i8 = 0;
+
+/// Extension type of unknown values with refinements.
+const REFINED_UNKNOWN_EXT_TYPE: i8 = 12;
+
+/// Keys of the refinements map, as defined by cty.
+const REFINEMENT_NULLNESS: i64 = 1;
+const REFINEMENT_STRING_PREFIX: i64 = 2;
+const REFINEMENT_NUMBER_LOWER_BOUND: i64 = 3;
+const REFINEMENT_NUMBER_UPPER_BOUND: i64 = 4;
+const REFINEMENT_LENGTH_LOWER_BOUND: i64 = 5;
+const REFINEMENT_LENGTH_UPPER_BOUND: i64 = 6;
+
+/// cty truncates longer string prefixes to keep refinements small.
+const MAX_STRING_PREFIX_LEN: usize = 256;
+
+pub(super) fn decode(bytes: &[u8], ty: Option<&Type>) -> Result<Value> {
+    let mut decoder = Decoder { bytes };
+    let value = decoder.value(ty)?;
+
+    if !decoder.bytes.is_empty() {
+        bail!("{} trailing bytes after msgpack value", decoder.bytes.len());
+    }
+
+    Ok(value)
+}
+
+pub(super) fn encode(value: &Value) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encode_value(&mut bytes, value);
+    bytes
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            bail!("unexpected end of msgpack value");
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take_array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take_array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take_array()?))
+    }
+
+    fn str(&mut self, len: usize) -> Result<String> {
+        let bytes = self.take(len)?;
+        let value = std::str::from_utf8(bytes).context("msgpack string is not valid utf-8")?;
+
+        Ok(value.to_string())
+    }
+
+    fn ext(&mut self, len: usize) -> Result<Value> {
+        let ext_type = self.u8()? as i8;
+        let payload = self.take(len)?;
+
+        match ext_type {
+            UNKNOWN_EXT_TYPE => Ok(Value::unknown()),
+            REFINED_UNKNOWN_EXT_TYPE => {
+                let mut decoder = Decoder { bytes: payload };
+                let refinements = decoder
+                    .refinements()
+                    .context("invalid refinements of unknown value")?;
+
+                Ok(Value::Unknown(refinements))
+            }
+            _ => bail!("unsupported msgpack extension type {ext_type}"),
+        }
+    }
+
+    /// Reads the map of refinements of an unknown value. Refinements this provider doesn't
+    /// know about are skipped, like cty does.
+    fn refinements(&mut self) -> Result<Refinements> {
+        let len = match Marker::from_u8(self.u8()?) {
+            Marker::FixMap(len) => len as usize,
+            Marker::Map16 => self.u16()? as usize,
+            Marker::Map32 => self.u32()? as usize,
+            _ => bail!("expected a map"),
+        };
+
+        let mut refinements = Refinements::default();
+
+        for _ in 0..len {
+            let key = match self.value(None)? {
+                Value::Number(key) => key.as_i64(),
+                _ => None,
+            }
+            .ok_or_else(|| anyhow!("expected an integer key"))?;
+
+            let value = self.value(None)?;
+
+            match key {
+                REFINEMENT_NULLNESS => {
+                    let Value::Bool(is_null) = value else {
+                        bail!("expected a bool for nullness, got {}", value.kind());
+                    };
+                    refinements.not_null = !is_null;
+                }
+                REFINEMENT_STRING_PREFIX => {
+                    let Value::String(prefix) = value else {
+                        bail!("expected a string prefix, got {}", value.kind());
+                    };
+                    refinements.string_prefix = Some(prefix);
+                }
+                REFINEMENT_NUMBER_LOWER_BOUND => {
+                    refinements.number_lower_bound = Some(number_bound(value)?);
+                }
+                REFINEMENT_NUMBER_UPPER_BOUND => {
+                    refinements.number_upper_bound = Some(number_bound(value)?);
+                }
+                REFINEMENT_LENGTH_LOWER_BOUND => {
+                    refinements.length_lower_bound = Some(length_bound(value)?);
+                }
+                REFINEMENT_LENGTH_UPPER_BOUND => {
+                    refinements.length_upper_bound = Some(length_bound(value)?);
+                }
+                _ => {}
+            }
+        }
+
+        if !self.bytes.is_empty() {
+            bail!("{} trailing bytes after refinements", self.bytes.len());
+        }
+
+        Ok(refinements)
+    }
+
+    fn value(&mut self, ty: Option<&Type>) -> Result<Value> {
+        let value = match Marker::from_u8(self.u8()?) {
+            Marker::Null => Value::Null,
+            Marker::True => Value::Bool(true),
+            Marker::False => Value::Bool(false),
+
+            Marker::FixPos(value) => Value::Number(value.into()),
+            Marker::FixNeg(value) => Value::Number(value.into()),
+            Marker::U8 => Value::Number(self.u8()?.into()),
+            Marker::U16 => Value::Number(self.u16()?.into()),
+            Marker::U32 => Value::Number(self.u32()?.into()),
+            Marker::U64 => Value::Number(self.u64()?.into()),
+            Marker::I8 => Value::Number((self.u8()? as i8).into()),
+            Marker::I16 => Value::Number((self.u16()? as i16).into()),
+            Marker::I32 => Value::Number((self.u32()? as i32).into()),
+            Marker::I64 => Value::Number((self.u64()? as i64).into()),
+            Marker::F32 => float(f32::from_bits(self.u32()?) as f64)?,
+            Marker::F64 => float(f64::from_bits(self.u64()?))?,
+
+            Marker::FixStr(len) => Value::String(self.str(len as usize)?),
+            Marker::Str8 => {
+                let len = self.u8()? as usize;
+                Value::String(self.str(len)?)
+            }
+            Marker::Str16 => {
+                let len = self.u16()? as usize;
+                Value::String(self.str(len)?)
+            }
+            Marker::Str32 => {
+                let len = self.u32()? as usize;
+                Value::String(self.str(len)?)
+            }
+
+            Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => {
+                bail!("unexpected binary data in msgpack value")
+            }
+
+            Marker::FixArray(len) => self.array(len as usize, ty)?,
+            Marker::Array16 => {
+                let len = self.u16()? as usize;
+                self.array(len, ty)?
+            }
+            Marker::Array32 => {
+                let len = self.u32()? as usize;
+                self.array(len, ty)?
+            }
+
+            Marker::FixMap(len) => self.map(len as usize, ty)?,
+            Marker::Map16 => {
+                let len = self.u16()? as usize;
+                self.map(len, ty)?
+            }
+            Marker::Map32 => {
+                let len = self.u32()? as usize;
+                self.map(len, ty)?
+            }
+
+            Marker::FixExt1 => self.ext(1)?,
+            Marker::FixExt2 => self.ext(2)?,
+            Marker::FixExt4 => self.ext(4)?,
+            Marker::FixExt8 => self.ext(8)?,
+            Marker::FixExt16 => self.ext(16)?,
+            Marker::Ext8 => {
+                let len = self.u8()? as usize;
+                self.ext(len)?
+            }
+            Marker::Ext16 => {
+                let len = self.u16()? as usize;
+                self.ext(len)?
+            }
+            Marker::Ext32 => {
+                let len = self.u32()? as usize;
+                self.ext(len)?
+            }
+
+            Marker::Reserved => bail!("invalid msgpack marker"),
+        };
+
+        match ty {
+            Some(ty) => conform(value, ty),
+            None => Ok(value),
+        }
+    }
+
+    fn array(&mut self, len: usize, ty: Option<&Type>) -> Result<Value> {
+        let element_type = |index: usize| match ty {
+            Some(Type::List(element) | Type::Set(element)) => Some(element.as_ref()),
+            Some(Type::Tuple(elements)) => elements.get(index),
+            _ => None,
+        };
+
+        if let Some(Type::Tuple(elements)) = ty {
+            if elements.len() != len {
+                bail!("expected a tuple of {} elements, got {len}", elements.len());
+            }
+        }
+
+        let elements = (0..len)
+            .map(|index| self.value(element_type(index)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(match ty {
+            Some(Type::List(_)) => Value::List(elements),
+            Some(Type::Set(_)) => Value::Set(elements),
+            _ => Value::Tuple(elements),
+        })
+    }
+
+    fn map(&mut self, len: usize, ty: Option<&Type>) -> Result<Value> {
+        let mut elements = BTreeMap::new();
+
+        for _ in 0..len {
+            let Value::String(name) = self.value(None)? else {
+                bail!("expected a string key in msgpack map");
+            };
+
+            let element_type = match ty {
+                Some(Type::Map(element)) => Some(element.as_ref()),
+                Some(Type::Object(attributes)) => Some(
+                    attributes
+                        .iter()
+                        .find(|(attribute, _)| *attribute == name)
+                        .map(|(_, ty)| ty)
+                        .ok_or_else(|| anyhow!("unexpected attribute `{name}`"))?,
+                ),
+                _ => None,
+            };
+
+            let element = self
+                .value(element_type)
+                .with_context(|| format!("in `{name}`"))?;
+            elements.insert(name, element);
+        }
+
+        Ok(match ty {
+            Some(Type::Map(_)) => Value::Map(elements),
+            _ => Value::Object(elements),
+        })
+    }
+}
+
+/// A number bound is encoded as the number followed by whether it is inclusive.
+fn number_bound(value: Value) -> Result<NumberBound> {
+    let Value::Tuple(elements) = value else {
+        bail!("expected a number bound, got {}", value.kind());
+    };
+
+    match <[Value; 2]>::try_from(elements) {
+        Ok([value, Value::Bool(inclusive)]) => match conform(value, &Type::Number)? {
+            Value::Number(value) => Ok(NumberBound { value, inclusive }),
+            value => bail!("expected a number bound, got {}", value.kind()),
+        },
+        _ => bail!("expected a number bound of a number and a bool"),
+    }
+}
+
+fn length_bound(value: Value) -> Result<u64> {
+    match value {
+        Value::Number(length) => length
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid length bound {length}")),
+        value => bail!("expected a length bound, got {}", value.kind()),
+    }
+}
+
+fn float(value: f64) -> Result<Value> {
+    Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| anyhow!("msgpack number {value} is not finite"))
+}
+
+/// Checks a decoded value against the type it should have. Containers already got their
+/// kind from the type while decoding.
+fn conform(value: Value, ty: &Type) -> Result<Value> {
+    match (value, ty) {
+        (value @ (Value::Null | Value::Unknown(_)), _) => Ok(value),
+        (value @ Value::Bool(_), Type::Bool) => Ok(value),
+        (value @ Value::String(_), Type::String) => Ok(value),
+        (value @ Value::Number(_), Type::Number) => Ok(value),
+        // numbers that don't fit a float are sent as strings
+        (Value::String(value), Type::Number) => value
+            .parse::<Number>()
+            .map(Value::Number)
+            .map_err(|_| anyhow!("`{value}` is not a number")),
+        (value @ (Value::List(_) | Value::Set(_) | Value::Tuple(_)), Type::List(_))
+        | (value @ (Value::List(_) | Value::Set(_) | Value::Tuple(_)), Type::Set(_))
+        | (value @ (Value::List(_) | Value::Set(_) | Value::Tuple(_)), Type::Tuple(_))
+        | (value @ (Value::Map(_) | Value::Object(_)), Type::Map(_))
+        | (value @ (Value::Map(_) | Value::Object(_)), Type::Object(_)) => Ok(value),
+        (value, ty) => bail!("expected a value of type {ty}, got {}", value.kind()),
+    }
+}
+
+fn encode_value(bytes: &mut Vec<u8>, value: &Value) {
+    // writing to a vec can't fail
+    match value {
+        Value::Null => {
+            let _ = encode::write_nil(bytes);
+        }
+        Value::Unknown(refinements) if refinements.is_empty() => {
+            let _ = encode::write_ext_meta(bytes, 1, UNKNOWN_EXT_TYPE);
+            bytes.push(0);
+        }
+        Value::Unknown(refinements) => {
+            let payload = encode_refinements(refinements);
+            let _ = encode::write_ext_meta(bytes, payload.len() as u32, REFINED_UNKNOWN_EXT_TYPE);
+            bytes.extend(payload);
+        }
+        Value::Bool(value) => {
+            let _ = encode::write_bool(bytes, *value);
+        }
+        Value::Number(value) => encode_number(bytes, value),
+        Value::String(value) => {
+            let _ = encode::write_str(bytes, value);
+        }
+        Value::List(elements) | Value::Set(elements) | Value::Tuple(elements) => {
+            let _ = encode::write_array_len(bytes, elements.len() as u32);
+            for element in elements {
+                encode_value(bytes, element);
+            }
+        }
+        Value::Map(elements) | Value::Object(elements) => {
+            let _ = encode::write_map_len(bytes, elements.len() as u32);
+            for (name, element) in elements {
+                let _ = encode::write_str(bytes, name);
+                encode_value(bytes, element);
+            }
+        }
+    }
+}
+
+fn encode_number(bytes: &mut Vec<u8>, value: &Number) {
+    if let Some(value) = value.as_i64() {
+        let _ = encode::write_sint(bytes, value);
+    } else if let Some(value) = value.as_u64() {
+        let _ = encode::write_uint(bytes, value);
+    } else if let Some(value) = value.as_f64() {
+        let _ = encode::write_f64(bytes, value);
+    }
+}
+
+fn encode_refinements(refinements: &Refinements) -> Vec<u8> {
+    let mut entries: Vec<(i64, Vec<u8>)> = Vec::new();
+
+    if refinements.not_null {
+        let mut value = Vec::new();
+        let _ = encode::write_bool(&mut value, false);
+        entries.push((REFINEMENT_NULLNESS, value));
+    }
+    if let Some(prefix) = &refinements.string_prefix {
+        let mut value = Vec::new();
+        let _ = encode::write_str(&mut value, truncate(prefix, MAX_STRING_PREFIX_LEN));
+        entries.push((REFINEMENT_STRING_PREFIX, value));
+    }
+    for (key, bound) in [
+        (
+            REFINEMENT_NUMBER_LOWER_BOUND,
+            &refinements.number_lower_bound,
+        ),
+        (
+            REFINEMENT_NUMBER_UPPER_BOUND,
+            &refinements.number_upper_bound,
+        ),
+    ] {
+        if let Some(bound) = bound {
+            let mut value = Vec::new();
+            let _ = encode::write_array_len(&mut value, 2);
+            encode_number(&mut value, &bound.value);
+            let _ = encode::write_bool(&mut value, bound.inclusive);
+            entries.push((key, value));
+        }
+    }
+    for (key, bound) in [
+        (
+            REFINEMENT_LENGTH_LOWER_BOUND,
+            refinements.length_lower_bound,
+        ),
+        (
+            REFINEMENT_LENGTH_UPPER_BOUND,
+            refinements.length_upper_bound,
+        ),
+    ] {
+        if let Some(bound) = bound {
+            let mut value = Vec::new();
+            let _ = encode::write_uint(&mut value, bound);
+            entries.push((key, value));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    let _ = encode::write_map_len(&mut bytes, entries.len() as u32);
+    for (key, value) in entries {
+        let _ = encode::write_sint(&mut bytes, key);
+        bytes.extend(value);
+    }
+    bytes
+}
+
+/// Shortens `value` to at most `max_len` bytes without splitting a character. A shorter
+/// prefix still holds for the same values.
+fn truncate(value: &str, max_len: usize) -> &str {
+    if value.len() <= max_len {
+        return value;
+    }
+
+    let end = (0..=max_len)
+        .rev()
+        .find(|&index| value.is_char_boundary(index))
+        .unwrap_or_default();
+
+    &value[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection, option, prelude::*};
+
+    use super::*;
+
+    fn arb_number() -> impl Strategy<Value = Number> {
+        prop_oneof![
+            any::<i64>().prop_map(Number::from),
+            any::<u64>().prop_map(Number::from),
+            any::<f64>().prop_filter_map("finite", Number::from_f64),
+        ]
+    }
+
+    /// Strings that are likely to trip up a scanner that doesn't follow the structure, like
+    /// ones containing extension and string markers.
+    fn arb_string() -> impl Strategy<Value = String> {
+        prop_oneof![
+            any::<String>(),
+            "[\\u{500}-\\u{53f}\\u{d4}<>a-z ]{0,40}",
+            Just("<unknown>".to_string()),
+            Just("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAINT\u{500}\u{0}".to_string()),
+        ]
+    }
+
+    fn arb_type() -> impl Strategy<Value = Type> {
+        let leaf = prop_oneof![Just(Type::String), Just(Type::Number), Just(Type::Bool)];
+
+        leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                inner.clone().prop_map(Type::list),
+                inner.clone().prop_map(Type::set),
+                inner.clone().prop_map(Type::map),
+                collection::btree_map("[a-z_]{1,8}", inner.clone(), 0..4).prop_map(Type::object),
+                collection::vec(inner, 0..4).prop_map(Type::Tuple),
+            ]
+        })
+    }
+
+    /// Refinements that make sense for a value of type `ty`. String prefixes are kept short
+    /// enough not to be truncated.
+    fn arb_refinements(ty: &Type) -> BoxedStrategy<Refinements> {
+        let not_null = any::<bool>();
+        let bound = || {
+            (arb_number(), any::<bool>())
+                .prop_map(|(value, inclusive)| NumberBound { value, inclusive })
+        };
+
+        match ty {
+            Type::String => (not_null, option::of("[\\u{500}-\\u{53f}a-z-]{0,40}"))
+                .prop_map(|(not_null, string_prefix)| Refinements {
+                    not_null,
+                    string_prefix,
+                    ..Default::default()
+                })
+                .boxed(),
+            Type::Number => (not_null, option::of(bound()), option::of(bound()))
+                .prop_map(
+                    |(not_null, number_lower_bound, number_upper_bound)| Refinements {
+                        not_null,
+                        number_lower_bound,
+                        number_upper_bound,
+                        ..Default::default()
+                    },
+                )
+                .boxed(),
+            Type::List(_) | Type::Set(_) | Type::Map(_) => {
+                (not_null, option::of(any::<u64>()), option::of(any::<u64>()))
+                    .prop_map(
+                        |(not_null, length_lower_bound, length_upper_bound)| Refinements {
+                            not_null,
+                            length_lower_bound,
+                            length_upper_bound,
+                            ..Default::default()
+                        },
+                    )
+                    .boxed()
+            }
+            _ => not_null
+                .prop_map(|not_null| Refinements {
+                    not_null,
+                    ..Default::default()
+                })
+                .boxed(),
+        }
+    }
+
+    fn arb_value(ty: &Type) -> BoxedStrategy<Value> {
+        let known = match ty {
+            Type::String => arb_string().prop_map(Value::String).boxed(),
+            Type::Number => arb_number().prop_map(Value::Number).boxed(),
+            Type::Bool => any::<bool>().prop_map(Value::Bool).boxed(),
+            Type::List(element) => collection::vec(arb_value(element), 0..4)
+                .prop_map(Value::List)
+                .boxed(),
+            Type::Set(element) => collection::vec(arb_value(element), 0..4)
+                .prop_map(Value::Set)
+                .boxed(),
+            Type::Map(element) => collection::btree_map(arb_string(), arb_value(element), 0..4)
+                .prop_map(Value::Map)
+                .boxed(),
+            Type::Object(attributes) => attributes
+                .iter()
+                .map(|(name, ty)| {
+                    let name = name.clone();
+                    arb_value(ty).prop_map(move |value| (name.clone(), value))
+                })
+                .collect::<Vec<_>>()
+                .prop_map(|attributes| Value::Object(attributes.into_iter().collect()))
+                .boxed(),
+            Type::Tuple(elements) => elements
+                .iter()
+                .map(arb_value)
+                .collect::<Vec<_>>()
+                .prop_map(Value::Tuple)
+                .boxed(),
+        };
+
+        prop_oneof![
+            1 => Just(Value::Null),
+            1 => arb_refinements(ty).prop_map(Value::Unknown),
+            6 => known,
+        ]
+        .boxed()
+    }
+
+    fn arb_typed_value() -> impl Strategy<Value = (Type, Value)> {
+        arb_type().prop_flat_map(|ty| {
+            let value = arb_value(&ty);
+            (Just(ty), value)
+        })
+    }
+
+    /// Values as they come out of decoding without a type.
+    fn arb_implied_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            Just(Value::unknown()),
+            any::<bool>().prop_map(Value::Bool),
+            arb_number().prop_map(Value::Number),
+            arb_string().prop_map(Value::String),
+        ];
+
+        leaf.prop_recursive(4, 64, 6, |inner| {
+            prop_oneof![
+                collection::vec(inner.clone(), 0..6).prop_map(Value::Tuple),
+                collection::btree_map(arb_string(), inner, 0..6).prop_map(Value::Object),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips_typed_values((ty, value) in arb_typed_value()) {
+            let bytes = value.to_msgpack();
+            prop_assert_eq!(Value::from_msgpack(&bytes, &ty).unwrap(), value);
+        }
+
+        #[test]
+        fn round_trips_values_without_type(value in arb_implied_value()) {
+            let bytes = value.to_msgpack();
+            prop_assert_eq!(Value::from_msgpack_implied(&bytes).unwrap(), value);
+        }
+
+        #[test]
+        fn never_panics_on_arbitrary_bytes(bytes in collection::vec(any::<u8>(), 0..64)) {
+            let _ = Value::from_msgpack_implied(&bytes);
+        }
+    }
+
+    #[test]
+    fn keeps_extension_marker_bytes_inside_other_values() {
+        // 0xd4 is the marker of the 3 byte unknown value, here it is part of a string, an
+        // integer and a map length
+        let value = Value::Object(BTreeMap::from([
+            (
+                "key".to_string(),
+                Value::String("\u{500}\u{0}\u{0}".to_string()),
+            ),
+            ("port".to_string(), Value::Number(0xd4.into())),
+            ("big".to_string(), Value::Number(0xd400_0000_i64.into())),
+            ("unknown".to_string(), Value::unknown()),
+        ]));
+
+        let bytes = value.to_msgpack();
+        assert!(bytes.windows(2).any(|window| window == [0xd4, 0x80]));
+
+        assert_eq!(Value::from_msgpack_implied(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn decodes_unknown_values_anywhere() {
+        // {"a": unknown, "b": [1, unknown], "c": "x"}
+        let bytes = [
+            0x83, 0xa1, b'a', 0xd4, 0x00, 0x00, 0xa1, b'b', 0x92, 0x01, 0xd4, 0x00, 0x00, 0xa1,
+            b'c', 0xa1, b'x',
+        ];
+
+        let ty = Type::object([
+            ("a", Type::Bool),
+            ("b", Type::list(Type::Number)),
+            ("c", Type::String),
+        ]);
+
+        let value = Value::from_msgpack(&bytes, &ty).unwrap();
+        assert_eq!(
+            value,
+            Value::Object(BTreeMap::from([
+                ("a".to_string(), Value::unknown()),
+                (
+                    "b".to_string(),
+                    Value::List(vec![Value::Number(1.into()), Value::unknown()])
+                ),
+                ("c".to_string(), Value::String("x".to_string())),
+            ]))
+        );
+
+        assert_eq!(value.to_msgpack(), bytes);
+    }
+
+    #[test]
+    fn decodes_unknown_values_with_longer_payloads() {
+        // ext 8 with a 2 byte payload, which only matters for refined unknowns
+        let bytes = [0xc7, 0x02, 0x00, 0x01, 0x02];
+        assert_eq!(
+            Value::from_msgpack(&bytes, &Type::String).unwrap(),
+            Value::unknown()
+        );
+    }
+
+    #[test]
+    fn decodes_refined_unknowns() {
+        // ext 12 with {1: false, 2: "web-"}: not null, starts with "web-"
+        let bytes = [
+            0xc7, 0x09, 0x0c, 0x82, 0x01, 0xc2, 0x02, 0xa4, b'w', b'e', b'b', b'-',
+        ];
+
+        let value = Value::from_msgpack(&bytes, &Type::String).unwrap();
+        assert_eq!(
+            value,
+            Value::Unknown(Refinements::default().not_null().string_prefix("web-"))
+        );
+        assert_eq!(value.to_msgpack(), bytes);
+    }
+
+    #[test]
+    fn decodes_bounds_and_skips_unsupported_refinements() {
+        // ext 12 with {3: [0, true], 4: [10, false], 5: 1, 9: "future"}
+        let bytes = [
+            0xc7, 0x13, 0x0c, 0x84, 0x03, 0x92, 0x00, 0xc3, 0x04, 0x92, 0x0a, 0xc2, 0x05, 0x01,
+            0x09, 0xa6, b'f', b'u', b't', b'u', b'r', b'e',
+        ];
+
+        assert_eq!(
+            Value::from_msgpack_implied(&bytes).unwrap(),
+            Value::Unknown(
+                Refinements::default()
+                    .number_lower_bound(0, true)
+                    .number_upper_bound(10, false)
+                    .length_lower_bound(1)
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_refinements() {
+        // ext 12 with {1: "no"}
+        let bytes = [0xc7, 0x05, 0x0c, 0x81, 0x01, 0xa2, b'n', b'o'];
+        assert!(Value::from_msgpack_implied(&bytes).is_err());
+
+        // ext 12 with a string instead of a map
+        let bytes = [0xd5, 0x0c, 0xa1, b'a'];
+        assert!(Value::from_msgpack_implied(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncates_long_string_prefixes() {
+        let prefix = "\u{500}".repeat(200);
+        let value = Value::Unknown(Refinements::default().string_prefix(&prefix));
+
+        let decoded = Value::from_msgpack_implied(&value.to_msgpack()).unwrap();
+        let Value::Unknown(Refinements {
+            string_prefix: Some(decoded_prefix),
+            ..
+        }) = decoded
+        else {
+            panic!("expected a refined unknown, got {decoded:?}");
+        };
+
+        assert_eq!(decoded_prefix.len(), MAX_STRING_PREFIX_LEN);
+        assert!(prefix.starts_with(&decoded_prefix));
+    }
+
+    #[test]
+    fn decodes_containers_by_type() {
+        let value = Value::Tuple(vec![Value::String("a".to_string())]);
+        let bytes = value.to_msgpack();
+
+        assert_eq!(
+            Value::from_msgpack(&bytes, &Type::set(Type::String)).unwrap(),
+            Value::Set(vec![Value::String("a".to_string())])
+        );
+        assert_eq!(Value::from_msgpack_implied(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn decodes_numbers_sent_as_strings() {
+        let bytes = Value::String("1.5".to_string()).to_msgpack();
+
+        assert_eq!(
+            Value::from_msgpack(&bytes, &Type::Number).unwrap(),
+            Value::Number(Number::from_f64(1.5).unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let bytes = Value::String("yes".to_string()).to_msgpack();
+        assert!(Value::from_msgpack(&bytes, &Type::Bool).is_err());
+
+        let bytes =
+            Value::Object(BTreeMap::from([("other".to_string(), Value::Bool(true))])).to_msgpack();
+        assert!(Value::from_msgpack(&bytes, &Type::object([("name", Type::String)])).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_bytes() {
+        assert!(Value::from_msgpack_implied(&[0xa3, b'a']).is_err());
+        assert!(Value::from_msgpack_implied(&[0xc0, 0xc0]).is_err());
+        assert!(Value::from_msgpack_implied(&[]).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD_NO_PAD as base64_engine, Engine as _};
+
+/// Environment variable Terraform sets to tell plugins they were started by it.
+pub const MAGIC_COOKIE_KEY: &str = "TF_PLUGIN_MAGIC_COOKIE";
+pub const MAGIC_COOKIE_VALUE: &str =
+    "d602bf8f470bc67ca7faa0386276bbdd4330efaf76d1a219cb4d6991ca9872b2";
+
+/// Environment variable with the comma separated protocol versions Terraform speaks.
+pub const PROTOCOL_VERSIONS_KEY: &str = "PLUGIN_PROTOCOL_VERSIONS";
+
+/// Version of the go-plugin handshake itself.
+pub const CORE_PROTOCOL_VERSION: u32 = 1;
+
+/// Versions of the Terraform plugin protocol this crate implements.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u32] = &[6];
+
+/// Printed when the binary is run by hand instead of by Terraform.
+pub const NOT_A_PLUGIN_MESSAGE: &str = "This binary is a plugin. These are not meant to be \
+    executed directly. Please execute the program that consumes these plugins, which will \
+    load any plugins automatically";
+
+/// Checks the value of `TF_PLUGIN_MAGIC_COOKIE`.
+pub fn check_magic_cookie(value: Option<&str>) -> Result<()> {
+    match value {
+        Some(MAGIC_COOKIE_VALUE) => Ok(()),
+        _ => bail!(NOT_A_PLUGIN_MESSAGE),
+    }
+}
+
+/// Picks the newest protocol version both sides speak from the value of
+/// `PLUGIN_PROTOCOL_VERSIONS`. Clients that don't send the variable get the newest version
+/// in `supported`.
+pub fn negotiate_protocol_version(client_versions: Option<&str>, supported: &[u32]) -> Result<u32> {
+    let newest = supported
+        .iter()
+        .copied()
+        .max()
+        .ok_or_else(|| anyhow!("no protocol versions are supported"))?;
+
+    let Some(client_versions) = client_versions.filter(|value| !value.trim().is_empty()) else {
+        return Ok(newest);
+    };
+
+    let client_versions = client_versions
+        .split(',')
+        .map(|version| {
+            version.trim().parse::<u32>().map_err(|_| {
+                anyhow!("invalid protocol version `{version}` in {PROTOCOL_VERSIONS_KEY}")
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    client_versions
+        .iter()
+        .copied()
+        .filter(|version| supported.contains(version))
+        .max()
+        .ok_or_else(|| {
+            anyhow!(
+                "incompatible plugin protocol versions, terraform supports {} and the provider supports {}",
+                join_versions(&client_versions),
+                join_versions(supported)
+            )
+        })
+}
+
+/// Runs the checks Terraform expects before a plugin starts serving and returns the
+/// protocol version to announce.
+pub fn negotiate_from_env() -> Result<u32> {
+    check_magic_cookie(std::env::var(MAGIC_COOKIE_KEY).ok().as_deref())?;
+
+    negotiate_protocol_version(
+        std::env::var(PROTOCOL_VERSIONS_KEY).ok().as_deref(),
+        SUPPORTED_PROTOCOL_VERSIONS,
+    )
+}
+
+fn join_versions(versions: &[u32]) -> String {
+    versions
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The line a plugin prints on stdout to tell Terraform where to connect, like
+/// `1|6|tcp|127.0.0.1:41235|grpc|<server certificate>`.
+pub struct HandshakeLine<'a> {
+    pub protocol_version: u32,
+    pub network: &'a str,
+    pub address: &'a str,
+    /// DER encoded certificate the server presents, for Terraform to trust.
+    pub server_cert: &'a [u8],
+}
+
+impl fmt::Display for HandshakeLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{CORE_PROTOCOL_VERSION}|{}|{}|{}|grpc|{}",
+            self.protocol_version,
+            self.network,
+            self.address,
+            base64_engine.encode(self.server_cert)
+        )
+    }
+}
+
+/// Tells Terraform where to find a provider that was started by hand, as the value of
+/// `TF_REATTACH_PROVIDERS`.
+pub struct ReattachConfig<'a> {
+    /// Full provider address, like `registry.terraform.io/ubicloud/ubicloud`.
+    pub provider_address: &'a str,
+    pub protocol_version: u32,
+    pub pid: u32,
+    pub network: &'a str,
+    pub address: &'a str,
+}
+
+impl fmt::Display for ReattachConfig<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config = serde_json::json!({
+            self.provider_address: {
+                "Protocol": "grpc",
+                "ProtocolVersion": self.protocol_version,
+                "Pid": self.pid,
+                "Test": true,
+                "Addr": {
+                    "Network": self.network,
+                    "String": self.address,
+                },
+            },
+        });
+
+        write!(f, "{config}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_terraform_magic_cookie() {
+        assert!(check_magic_cookie(Some(MAGIC_COOKIE_VALUE)).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_magic_cookie() {
+        for value in [None, Some(""), Some("not-the-cookie")] {
+            let err = check_magic_cookie(value).unwrap_err();
+            assert_eq!(err.to_string(), NOT_A_PLUGIN_MESSAGE);
+        }
+    }
+
+    #[test]
+    fn picks_newest_common_version() {
+        assert_eq!(negotiate_protocol_version(Some("5,6"), &[6]).unwrap(), 6);
+        assert_eq!(
+            negotiate_protocol_version(Some("6, 5"), &[5, 6]).unwrap(),
+            6
+        );
+        assert_eq!(negotiate_protocol_version(Some("4,5"), &[5, 6]).unwrap(), 5);
+    }
+
+    #[test]
+    fn defaults_to_newest_supported_version() {
+        assert_eq!(negotiate_protocol_version(None, &[5, 6]).unwrap(), 6);
+        assert_eq!(negotiate_protocol_version(Some(""), &[5, 6]).unwrap(), 6);
+    }
+
+    #[test]
+    fn fails_without_common_version() {
+        let err = negotiate_protocol_version(Some("4,5"), &[6]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "incompatible plugin protocol versions, terraform supports 4, 5 and the provider supports 6"
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_version() {
+        assert!(negotiate_protocol_version(Some("5,six"), &[6]).is_err());
+    }
+
+    #[test]
+    fn formats_handshake_line() {
+        let line = HandshakeLine {
+            protocol_version: 6,
+            network: "tcp",
+            address: "127.0.0.1:1100",
+            server_cert: &[0xde, 0xad, 0xbe, 0xef],
+        };
+
+        assert_eq!(line.to_string(), "1|6|tcp|127.0.0.1:1100|grpc|3q2+7w");
+    }
+
+    #[test]
+    fn formats_reattach_config() {
+        let config = ReattachConfig {
+            provider_address: "registry.terraform.io/ubicloud/ubicloud",
+            protocol_version: 6,
+            pid: 4242,
+            network: "unix",
+            address: "/tmp/plugin1234",
+        };
+
+        let value: serde_json::Value = serde_json::from_str(&config.to_string()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "registry.terraform.io/ubicloud/ubicloud": {
+                    "Protocol": "grpc",
+                    "ProtocolVersion": 6,
+                    "Pid": 4242,
+                    "Test": true,
+                    "Addr": { "Network": "unix", "String": "/tmp/plugin1234" },
+                },
+            })
+        );
+    }
+}
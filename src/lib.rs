@@ -0,0 +1,19 @@
+//! Building blocks for Terraform providers written in Rust.
+//!
+//! A provider declares its configuration, resources and data sources with [`model!`],
+//! implements [`Resource`](resource::Resource) and [`DataSource`](resource::DataSource)
+//! for them and hands a [`Provider`](server::Provider) to [`serve`](server::serve),
+//! which speaks the Terraform plugin protocol (version 6) over gRPC.
+
+pub mod cty;
+pub mod handshake;
+pub mod listener;
+pub mod logging;
+pub mod plugin;
+pub mod resource;
+pub mod schema;
+pub mod server;
+pub mod tls;
+pub mod util;
+
+pub use tonic;
@@ -0,0 +1,109 @@
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+#[cfg(unix)]
+use crate::util::random_hex_suffix;
+
+/// Environment variable Terraform sets to the directory plugins should create their Unix
+/// socket in.
+pub const UNIX_SOCKET_DIR_KEY: &str = "PLUGIN_UNIX_SOCKET_DIR";
+
+/// An accepted connection, over TCP or a Unix socket.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Connection for T {}
+
+/// Where the provider accepts connections from Terraform.
+pub enum Listener {
+    /// A TCP port on the loopback interface, chosen by the OS.
+    Tcp(TcpListener),
+
+    /// A Unix socket, removed again when the listener is dropped.
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+impl Listener {
+    /// Binds a Unix socket when Terraform asked for one with `PLUGIN_UNIX_SOCKET_DIR` and
+    /// an ephemeral TCP port otherwise.
+    pub async fn bind_from_env() -> Result<Self> {
+        match std::env::var_os(UNIX_SOCKET_DIR_KEY) {
+            #[cfg(unix)]
+            Some(dir) if !dir.is_empty() => Self::bind_unix(PathBuf::from(dir)),
+            _ => Self::bind_tcp().await,
+        }
+    }
+
+    pub async fn bind_tcp() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .context("failed to bind a tcp port")?;
+
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Binds a socket with a random name in `dir`.
+    #[cfg(unix)]
+    pub fn bind_unix(dir: PathBuf) -> Result<Self> {
+        let path = dir.join(format!("plugin{}", random_hex_suffix(8)));
+
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind unix socket `{}`", path.display()))?;
+
+        Ok(Listener::Unix { listener, path })
+    }
+
+    /// Network type as announced in the handshake line.
+    pub fn network(&self) -> &'static str {
+        match self {
+            Listener::Tcp(_) => "tcp",
+            #[cfg(unix)]
+            Listener::Unix { .. } => "unix",
+        }
+    }
+
+    /// Address as announced in the handshake line.
+    pub fn address(&self) -> Result<String> {
+        match self {
+            Listener::Tcp(listener) => Ok(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => Ok(path.display().to_string()),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _addr) = listener.accept().await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
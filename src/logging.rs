@@ -0,0 +1,558 @@
+//! Logging in the format Terraform expects from plugins.
+//!
+//! Terraform reads the stderr of the plugins it starts, and shows lines that are
+//! hclog-style JSON objects in its own log output, tagged with their `@module`. It also
+//! takes care of `TF_LOG_PATH` for them, so plugins only write to stderr.
+//!
+//! Attributes marked `sensitive` in the schema never show up in the logs: models are logged
+//! through [`Redacted`], and the values of sensitive attributes the provider received are
+//! masked in every line written by the subscribers set up here.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    sync::{Mutex, RwLock},
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    level_filters::LevelFilter,
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        MakeWriter,
+    },
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::schema::{Block, Model};
+
+/// Log level for providers, overrides `TF_LOG`.
+pub const LOG_PROVIDER_KEY: &str = "TF_LOG_PROVIDER";
+pub const LOG_KEY: &str = "TF_LOG";
+
+/// File Terraform appends its logs to.
+pub const LOG_PATH_KEY: &str = "TF_LOG_PATH";
+
+/// Logged in place of sensitive values.
+pub const REDACTED: &str = "<sensitive>";
+
+/// Secrets shorter than this are only masked where they stand alone rather than inside a
+/// longer word, which would mask unrelated text all over the logs, like every `a` for a one
+/// letter secret.
+const MIN_SUBSTRING_SECRET_LEN: usize = 6;
+
+/// Values of sensitive attributes seen so far, masked in every log line.
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Parses a Terraform log level. `JSON` is Terraform's trace level with JSON output and
+/// unknown levels fall back to trace, as in Terraform itself.
+pub fn parse_level(value: &str) -> LevelFilter {
+    match value.trim().to_ascii_uppercase().as_str() {
+        "" | "OFF" => LevelFilter::OFF,
+        "ERROR" => LevelFilter::ERROR,
+        "WARN" => LevelFilter::WARN,
+        "INFO" => LevelFilter::INFO,
+        "DEBUG" => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// The level set through `TF_LOG_PROVIDER` or `TF_LOG`, if any.
+pub fn level_from_env() -> Option<LevelFilter> {
+    [LOG_PROVIDER_KEY, LOG_KEY]
+        .into_iter()
+        .find_map(|key| std::env::var(key).ok().filter(|value| !value.is_empty()))
+        .map(|value| parse_level(&value))
+}
+
+/// Sets up logging for a plugin started by Terraform, as hclog JSON on stderr tagged with
+/// `module`. Nothing is logged unless `TF_LOG` or `TF_LOG_PROVIDER` ask for it.
+pub fn init(module: &'static str) {
+    let level = level_from_env().unwrap_or(LevelFilter::OFF);
+
+    tracing_subscriber::registry()
+        .with(HclogLayer::new(module, Redacting(io::stderr)).with_filter(level))
+        .init();
+}
+
+/// Sets up logging for a provider started by hand, as human readable lines on stderr at
+/// info level unless `TF_LOG` or `TF_LOG_PROVIDER` say otherwise.
+///
+/// Terraform can't collect these logs, so they are also appended to `TF_LOG_PATH` as hclog
+/// JSON when it is set. A log file that can't be opened is reported and skipped.
+pub fn init_debug(module: &'static str) {
+    let level = level_from_env().unwrap_or(LevelFilter::INFO);
+
+    let log_file = std::env::var_os(LOG_PATH_KEY).and_then(|path| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| {
+                eprintln!(
+                    "failed to open log file `{}`: {err}",
+                    path.to_string_lossy()
+                )
+            })
+            .ok()
+    });
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(Redacting(io::stderr))
+                .with_filter(level),
+        )
+        .with(log_file.map(|log_file| {
+            HclogLayer::new(module, Redacting(Mutex::new(log_file))).with_filter(level)
+        }))
+        .init();
+}
+
+/// Remembers the values of the sensitive attributes of `value`, so they are masked wherever
+/// they end up in a log line, like in an error message that quotes them.
+pub fn register_secrets<T: Model + Serialize>(value: &T) {
+    let Ok(value) = serde_json::to_value(value) else {
+        return;
+    };
+
+    let mut found = vec![];
+    collect_secrets(&T::block(), &value, &mut found);
+
+    if found.is_empty() {
+        return;
+    }
+
+    let Ok(mut secrets) = SECRETS.write() else {
+        return;
+    };
+
+    for secret in found {
+        if !secrets.contains(&secret) {
+            secrets.push(secret);
+        }
+    }
+
+    // longest first, so a secret containing another one is masked as a whole
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+}
+
+fn collect_secrets(block: &Block, value: &Value, found: &mut Vec<String>) {
+    let Value::Object(attributes) = value else {
+        return;
+    };
+
+    for name in block.sensitive_attributes() {
+        if let Some(value) = attributes.get(name) {
+            collect_strings(value, found);
+        }
+    }
+
+    for (name, nested) in block.blocks() {
+        if let Some(value) = attributes.get(name) {
+            collect_secrets(nested, value, found);
+        }
+    }
+}
+
+fn collect_strings(value: &Value, found: &mut Vec<String>) {
+    match value {
+        Value::String(value) if !value.is_empty() => found.push(value.clone()),
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_strings(value, found)),
+        Value::Object(values) => values
+            .values()
+            .for_each(|value| collect_strings(value, found)),
+        _ => {}
+    }
+}
+
+/// Masks the registered secrets in `text`.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let Ok(secrets) = SECRETS.read() else {
+        return Cow::Borrowed(text);
+    };
+
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets.iter() {
+        if !text.contains(secret.as_str()) {
+            continue;
+        }
+
+        text = if secret.len() >= MIN_SUBSTRING_SECRET_LEN {
+            Cow::Owned(text.replace(secret.as_str(), REDACTED))
+        } else {
+            Cow::Owned(replace_standalone(&text, secret))
+        };
+    }
+
+    text
+}
+
+/// Masks the occurrences of `secret` in `text` that are not part of a longer word.
+fn replace_standalone(text: &str, secret: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut masked = String::with_capacity(text.len());
+    let mut end_of_last = 0;
+
+    for (start, _) in text.match_indices(secret) {
+        let end = start + secret.len();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+
+        if before.is_some_and(is_word) || after.is_some_and(is_word) {
+            continue;
+        }
+
+        masked.push_str(&text[end_of_last..start]);
+        masked.push_str(REDACTED);
+        end_of_last = end;
+    }
+
+    masked.push_str(&text[end_of_last..]);
+    masked
+}
+
+/// Formats a model for the logs, as JSON with its sensitive attributes masked.
+pub struct Redacted<'a, T>(pub &'a T);
+
+impl<T: Model + Serialize> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Ok(mut value) = serde_json::to_value(self.0) else {
+            return f.write_str(REDACTED);
+        };
+
+        mask_sensitive_attributes(&T::block(), &mut value);
+
+        write!(f, "{value}")
+    }
+}
+
+fn mask_sensitive_attributes(block: &Block, value: &mut Value) {
+    let Value::Object(attributes) = value else {
+        return;
+    };
+
+    for name in block.sensitive_attributes() {
+        if let Some(value) = attributes.get_mut(name).filter(|value| !value.is_null()) {
+            *value = REDACTED.into();
+        }
+    }
+
+    for (name, nested) in block.blocks() {
+        if let Some(value) = attributes.get_mut(name) {
+            mask_sensitive_attributes(nested, value);
+        }
+    }
+}
+
+/// Wraps a `tracing_subscriber` writer so registered secrets are masked in what is written
+/// through it. Relies on every line being written at once, as the fmt and hclog layers do.
+#[derive(Clone)]
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&text).as_bytes())?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Writes events as hclog JSON lines, with the fields of the spans they happen in.
+pub struct HclogLayer<W> {
+    module: &'static str,
+    make_writer: W,
+}
+
+impl<W> HclogLayer<W> {
+    pub fn new(module: &'static str, make_writer: W) -> Self {
+        Self {
+            module,
+            make_writer,
+        }
+    }
+}
+
+/// Span fields, kept in the span's extensions.
+struct SpanFields(Map<String, Value>);
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+fn hclog_level(level: &Level) -> &'static str {
+    match *level {
+        Level::TRACE => "trace",
+        Level::DEBUG => "debug",
+        Level::INFO => "info",
+        Level::WARN => "warn",
+        Level::ERROR => "error",
+    }
+}
+
+impl<S, W> Layer<S> for HclogLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut JsonVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut line = Map::new();
+
+        let mut timestamp = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+
+        line.insert(
+            "@level".into(),
+            hclog_level(event.metadata().level()).into(),
+        );
+        line.insert("@module".into(), self.module.into());
+        line.insert("@timestamp".into(), timestamp.into());
+
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    line.extend(fields.clone());
+                }
+            }
+        }
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+
+        let message = fields.remove("message").unwrap_or_default();
+        line.insert("@message".into(), message);
+        line.extend(fields);
+
+        let mut line = Value::Object(line).to_string();
+        line.push('\n');
+
+        let _ = self.make_writer.make_writer().write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde::Deserialize;
+    use tracing::info_span;
+
+    use super::*;
+    use crate::model;
+
+    model! {
+        #[schema]
+        #[derive(Debug, Deserialize, Serialize, PartialEq)]
+        struct TestCredentials {
+            #[schema(required)]
+            user: String,
+
+            #[schema(required, sensitive)]
+            token: String,
+        }
+    }
+
+    model! {
+        #[schema]
+        #[derive(Debug, Deserialize, Serialize)]
+        struct TestConfig {
+            #[schema(required)]
+            name: String,
+
+            #[schema(required, sensitive)]
+            password: String,
+
+            #[schema(block)]
+            credentials: TestCredentials,
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parses_terraform_log_levels() {
+        assert_eq!(parse_level("trace"), LevelFilter::TRACE);
+        assert_eq!(parse_level("DEBUG"), LevelFilter::DEBUG);
+        assert_eq!(parse_level("Info"), LevelFilter::INFO);
+        assert_eq!(parse_level("WARN"), LevelFilter::WARN);
+        assert_eq!(parse_level("ERROR"), LevelFilter::ERROR);
+        assert_eq!(parse_level("off"), LevelFilter::OFF);
+        assert_eq!(parse_level("JSON"), LevelFilter::TRACE);
+        assert_eq!(parse_level("verbose"), LevelFilter::TRACE);
+    }
+
+    #[test]
+    fn writes_hclog_json_with_span_fields() {
+        let buffer = Buffer::default();
+        let make_writer = {
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        };
+
+        let subscriber =
+            tracing_subscriber::registry().with(HclogLayer::new("provider.test", make_writer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = info_span!(
+                "rpc",
+                tf_rpc = "ReadResource",
+                tf_resource_type = "test_thing"
+            )
+            .entered();
+
+            tracing::warn!(attempt = 2, "vm is not running yet");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim_end()).unwrap();
+
+        assert_eq!(line["@level"], "warn");
+        assert_eq!(line["@module"], "provider.test");
+        assert_eq!(line["@message"], "vm is not running yet");
+        assert_eq!(line["tf_rpc"], "ReadResource");
+        assert_eq!(line["tf_resource_type"], "test_thing");
+        assert_eq!(line["attempt"], 2);
+        assert!(line["@timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn keeps_sensitive_values_out_of_logs() {
+        let buffer = Buffer::default();
+        let make_writer = {
+            let buffer = buffer.clone();
+            Redacting(move || buffer.clone())
+        };
+
+        let subscriber = tracing_subscriber::registry()
+            .with(HclogLayer::new("provider.test", make_writer.clone()))
+            .with(tracing_subscriber::fmt::layer().with_writer(make_writer));
+
+        let config = TestConfig {
+            name: "test-config".to_string(),
+            password: "hunter2-password".to_string(),
+            credentials: TestCredentials {
+                user: "test-user".to_string(),
+                token: "s3cr3t-token".to_string(),
+            },
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            register_secrets(&config);
+
+            tracing::info!("config: {:?}", Redacted(&config));
+            tracing::warn!(
+                password = config.password.as_str(),
+                "login failed with {}",
+                config.credentials.token
+            );
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+        assert!(output.contains("test-config"));
+        assert!(output.contains("test-user"));
+        assert!(output.contains(REDACTED));
+        assert!(!output.contains("hunter2-password"));
+        assert!(!output.contains("s3cr3t-token"));
+    }
+
+    #[test]
+    fn masks_short_secrets_where_they_stand_alone() {
+        register_secrets(&TestConfig {
+            name: "test-config".to_string(),
+            password: "pw1".to_string(),
+            credentials: TestCredentials {
+                user: "test-user".to_string(),
+                token: "x".to_string(),
+            },
+        });
+
+        assert_eq!(
+            redact("login with pw1 failed: password=pw1&token=x"),
+            format!("login with {REDACTED} failed: password={REDACTED}&token={REDACTED}")
+        );
+        assert_eq!(redact("pw12 exited with 1x"), "pw12 exited with 1x");
+    }
+}
@@ -0,0 +1,178 @@
+//! The go-plugin services Terraform expects next to the provider service.
+
+use std::{
+    io::{self, Write},
+    sync::OnceLock,
+};
+
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Result, Status};
+use tracing::info;
+
+pub mod proto {
+    #![allow(dead_code)]
+
+    tonic::include_proto!("plugin");
+}
+
+use proto::stdio_data::Channel;
+
+/// Lets Terraform shut the plugin down once it is done with it.
+pub struct Controller {
+    shutdown: CancellationToken,
+}
+
+impl Controller {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        Self { shutdown }
+    }
+}
+
+#[tonic::async_trait]
+impl proto::grpc_controller_server::GrpcController for Controller {
+    async fn shutdown(&self, _request: Request<proto::Empty>) -> Result<Response<proto::Empty>> {
+        info!("shutdown requested by terraform");
+
+        self.shutdown.cancel();
+
+        Ok(Response::new(proto::Empty {}))
+    }
+}
+
+/// Output chunks that are kept while Terraform has not connected to the stdio stream yet.
+/// Output beyond that is dropped rather than blocking the writer.
+const STDIO_BUFFER: usize = 1024;
+
+/// Output written through [`stdout`] and [`stderr`], streamed to the go-plugin client which
+/// copies it to the stdout and stderr writers it was configured with. Logs don't go here,
+/// they are written to stderr as hclog JSON, see [`crate::logging`]. Stdio is process
+/// wide, so there is a single one.
+struct Stdio {
+    sender: mpsc::Sender<proto::StdioData>,
+    receiver: Mutex<Option<mpsc::Receiver<proto::StdioData>>>,
+}
+
+fn stdio() -> &'static Stdio {
+    static STDIO: OnceLock<Stdio> = OnceLock::new();
+
+    STDIO.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel(STDIO_BUFFER);
+
+        Stdio {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    })
+}
+
+/// Serves the stdio stream, which Terraform connects to once per plugin process.
+#[derive(Default)]
+pub struct StdioService;
+
+#[tonic::async_trait]
+impl proto::grpc_stdio_server::GrpcStdio for StdioService {
+    type StreamStdioStream = ReceiverStream<std::result::Result<proto::StdioData, Status>>;
+
+    async fn stream_stdio(
+        &self,
+        _request: Request<()>,
+    ) -> Result<Response<Self::StreamStdioStream>> {
+        let Some(mut receiver) = stdio().receiver.lock().await.take() else {
+            return Err(Status::failed_precondition(
+                "stdio is already being streamed",
+            ));
+        };
+
+        let (sender, stream) = mpsc::channel(STDIO_BUFFER);
+
+        tokio::spawn(async move {
+            while let Some(data) = receiver.recv().await {
+                if sender.send(Ok(data)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// Writes to one channel of the stdio stream. Works as a `tracing_subscriber` writer.
+#[derive(Clone, Copy)]
+pub struct StdioWriter {
+    channel: Channel,
+}
+
+impl io::Write for StdioWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = stdio().sender.try_send(proto::StdioData {
+            channel: self.channel as i32,
+            data: buf.to_vec(),
+        });
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn stdout() -> StdioWriter {
+    StdioWriter {
+        channel: Channel::Stdout,
+    }
+}
+
+pub fn stderr() -> StdioWriter {
+    StdioWriter {
+        channel: Channel::Stderr,
+    }
+}
+
+/// Forwards panic reports to the stdio stream, after reporting them as usual. They are the
+/// output of the plugin that isn't hclog JSON and would otherwise only end up on stderr.
+pub fn forward_panics() {
+    let report = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        report(info);
+        let _ = writeln!(stderr(), "{info}");
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use proto::grpc_stdio_server::GrpcStdio;
+
+    #[tokio::test]
+    async fn streams_what_is_written_to_stdout_and_stderr() {
+        write!(stdout(), "out").unwrap();
+        write!(stderr(), "err").unwrap();
+
+        let mut stream = StdioService
+            .stream_stdio(Request::new(()))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let data = stream.next().await.unwrap().unwrap();
+        assert_eq!(data.channel, Channel::Stdout as i32);
+        assert_eq!(data.data, b"out");
+
+        let data = stream.next().await.unwrap().unwrap();
+        assert_eq!(data.channel, Channel::Stderr as i32);
+        assert_eq!(data.data, b"err");
+
+        let err = StdioService
+            .stream_stdio(Request::new(()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    }
+}
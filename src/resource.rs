@@ -0,0 +1,922 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::Response;
+use tracing::info;
+
+use crate::{
+    bail_with_diagnostic,
+    cty::{self, Value},
+    logging::{register_secrets, Redacted},
+    schema::{Diff, Model},
+    server::tf,
+    util::{
+        decode_dynamic_value, deserialize_dynamic_value, deserialize_optional_dynamic_value,
+        serialize_dynamic_value, IntoDynamicValue,
+    },
+};
+
+/// Diagnostics reported by a hook next to its result, like warnings about a resource that
+/// was only partially created.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub diagnostics: Vec<tf::Diagnostic>,
+}
+
+/// A managed resource type, like `ubicloud_vm`.
+///
+/// The registry decodes the values Terraform sends, works out which action to take and
+/// reports errors, so implementations only deal with their typed config and state.
+#[tonic::async_trait]
+pub trait Resource: Send + Sync + 'static {
+    type Config: Model + Diff + DeserializeOwned + Serialize + Clone + Debug + Send + Sync;
+    type State: Model + DeserializeOwned + Serialize + Clone + Debug + Send + Sync;
+
+    /// The configuration a state was created from.
+    fn config(state: &Self::State) -> &Self::Config;
+
+    /// Whether changing `attribute`, which the schema says forces a replacement, from its
+    /// value in `prior_state` to the one in `config` really needs a new resource. Lets a
+    /// resource ignore changes from values it couldn't read back, like after an import.
+    fn forces_replacement(
+        _attribute: &str,
+        _prior_state: &Self::State,
+        _config: &Self::Config,
+    ) -> bool {
+        true
+    }
+
+    /// Checks a configuration beyond what the schema can express. Optional values that are
+    /// not known yet are `None` here. Configurations with unknown values in other fields than
+    /// optional and [`MaybeUnknown`](crate::cty::MaybeUnknown) ones can't be decoded and are
+    /// not validated.
+    fn validate(&self, _config: &Self::Config, _diagnostics: &mut Diagnostics) {}
+
+    /// The state planned for a new resource, with computed attributes set to unknown.
+    fn plan_create(&self, config: Self::Config) -> Self::State;
+
+    /// The state planned when the resource is updated in place.
+    fn plan_update(&self, prior_state: Self::State, config: Self::Config) -> Self::State;
+
+    /// Refreshes a state, returning `None` when the resource no longer exists.
+    async fn read(&self, state: Self::State) -> Result<Option<Self::State>>;
+
+    async fn create(
+        &self,
+        planned_state: Self::State,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Self::State>;
+
+    async fn update(
+        &self,
+        prior_state: Self::State,
+        planned_state: Self::State,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Self::State>;
+
+    async fn delete(&self, prior_state: Self::State, diagnostics: &mut Diagnostics) -> Result<()>;
+
+    /// Builds the state of an existing resource from the id given to `terraform import`.
+    async fn import(&self, _id: &str) -> Result<Self::State> {
+        bail!("importing is not supported");
+    }
+}
+
+/// A data source type, like `ubicloud_vms`.
+#[tonic::async_trait]
+pub trait DataSource: Send + Sync + 'static {
+    type Config: DeserializeOwned + Debug + Send;
+    type State: Model + Serialize + Debug + Send;
+
+    async fn read(&self, config: Self::Config) -> Result<Self::State>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceAction {
+    Create,
+    Update,
+
+    /// Only planned. Terraform applies a replacement as a delete followed by a create.
+    Replace,
+
+    Delete,
+}
+
+pub struct ResourceState<R: Resource> {
+    pub did_change: bool,
+    pub action: ResourceAction,
+    pub changed_attributes: Vec<&'static str>,
+
+    /// The changed attributes that force the resource to be replaced.
+    pub requires_replace: Vec<&'static str>,
+
+    pub prior_state: Option<R::State>,
+    pub config: Option<R::Config>,
+    pub planned_state: Option<R::State>,
+}
+
+/// Works out what has to happen to a resource from the values Terraform sent along.
+///
+/// `planned_state` is only known when applying; when it is `None` the action is derived
+/// from `config` alone, as during planning.
+pub fn compute_resource_state<R: Resource>(
+    prior_state: Option<tf::DynamicValue>,
+    config: Option<tf::DynamicValue>,
+    planned_state: Option<tf::DynamicValue>,
+) -> Result<ResourceState<R>> {
+    let is_apply = planned_state.is_some();
+
+    let prior_state = deserialize_optional_dynamic_value::<R::State>(prior_state)?;
+    let config = deserialize_optional_dynamic_value::<R::Config>(config)?;
+    let planned_state = deserialize_optional_dynamic_value::<R::State>(planned_state)?;
+
+    register_secrets(&prior_state);
+    register_secrets(&config);
+    register_secrets(&planned_state);
+
+    let target_config = if is_apply {
+        planned_state.as_ref().map(R::config)
+    } else {
+        config.as_ref()
+    };
+
+    let (changed_attributes, requires_replace) = match (&prior_state, target_config) {
+        (Some(prior_state), Some(target_config)) => {
+            let changed_attributes = R::config(prior_state).changed_attributes(target_config);
+            let replace_attributes = R::Config::block().replace_attributes();
+
+            let requires_replace = changed_attributes
+                .iter()
+                .copied()
+                .filter(|name| replace_attributes.contains(name))
+                .filter(|name| R::forces_replacement(name, prior_state, target_config))
+                .collect();
+
+            (changed_attributes, requires_replace)
+        }
+        _ => (vec![], vec![]),
+    };
+
+    let action = match (&prior_state, target_config) {
+        (None, None) => bail!("neither prior state nor config is present"),
+        (None, Some(_)) => ResourceAction::Create,
+        (Some(_), None) => ResourceAction::Delete,
+        (Some(_), Some(_)) if requires_replace.is_empty() => ResourceAction::Update,
+        (Some(_), Some(_)) => ResourceAction::Replace,
+    };
+
+    let did_change = !matches!(action, ResourceAction::Update) || !changed_attributes.is_empty();
+
+    Ok(ResourceState {
+        did_change,
+        action,
+        changed_attributes,
+        requires_replace,
+        prior_state,
+        config,
+        planned_state,
+    })
+}
+
+/// Object safe side of [`Resource`], handling requests for one resource type.
+#[tonic::async_trait]
+pub trait AnyResource: Send + Sync {
+    fn schema(&self) -> tf::Schema;
+
+    async fn validate(
+        &self,
+        request: tf::validate_resource_config::Request,
+    ) -> tonic::Result<Response<tf::validate_resource_config::Response>>;
+
+    async fn read(
+        &self,
+        request: tf::read_resource::Request,
+    ) -> tonic::Result<Response<tf::read_resource::Response>>;
+
+    async fn plan(
+        &self,
+        request: tf::plan_resource_change::Request,
+    ) -> tonic::Result<Response<tf::plan_resource_change::Response>>;
+
+    async fn apply(
+        &self,
+        request: tf::apply_resource_change::Request,
+    ) -> tonic::Result<Response<tf::apply_resource_change::Response>>;
+
+    async fn import(
+        &self,
+        request: tf::import_resource_state::Request,
+    ) -> tonic::Result<Response<tf::import_resource_state::Response>>;
+
+    async fn upgrade(
+        &self,
+        request: tf::upgrade_resource_state::Request,
+    ) -> tonic::Result<Response<tf::upgrade_resource_state::Response>>;
+}
+
+#[tonic::async_trait]
+impl<R: Resource> AnyResource for R {
+    fn schema(&self) -> tf::Schema {
+        R::State::schema()
+    }
+
+    async fn validate(
+        &self,
+        request: tf::validate_resource_config::Request,
+    ) -> tonic::Result<Response<tf::validate_resource_config::Response>> {
+        let mut diagnostics = Diagnostics::default();
+
+        let config = request.config.unwrap_or_default();
+
+        // configs with values that are not known yet may not deserialize, those are not
+        // validated here and invalid values in them are reported where they are used
+        if let Ok(config) = deserialize_dynamic_value::<R::Config>(config) {
+            Resource::validate(self, &config, &mut diagnostics);
+        }
+
+        Ok(Response::new(tf::validate_resource_config::Response {
+            diagnostics: diagnostics.diagnostics,
+        }))
+    }
+
+    async fn read(
+        &self,
+        request: tf::read_resource::Request,
+    ) -> tonic::Result<Response<tf::read_resource::Response>> {
+        let mut response = tf::read_resource::Response::default();
+
+        let current_state = request.current_state.unwrap_or_default();
+
+        let current_state = match deserialize_dynamic_value::<Option<R::State>>(current_state) {
+            Ok(current_state) => current_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to deserialize current state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        register_secrets(&current_state);
+
+        let Some(current_state) = current_state else {
+            response.new_state = Value::Null.to_msgpack().into_dynamic_value().into();
+            return Ok(Response::new(response));
+        };
+
+        let new_state = match Resource::read(self, current_state).await {
+            Ok(new_state) => new_state,
+            Err(err) => {
+                bail_with_diagnostic!(response, err, format!("{err:#}"));
+            }
+        };
+
+        info!("new_state: {:?}", Redacted(&new_state));
+
+        let new_state = match serialize_dynamic_value(&new_state) {
+            Ok(new_state) => new_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to serialize new state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        response.new_state = new_state.into_dynamic_value().into();
+        Ok(Response::new(response))
+    }
+
+    async fn plan(
+        &self,
+        request: tf::plan_resource_change::Request,
+    ) -> tonic::Result<Response<tf::plan_resource_change::Response>> {
+        let mut response = tf::plan_resource_change::Response::default();
+
+        let config = match decode_dynamic_value(&request.config.clone().unwrap_or_default()) {
+            Ok(config) => config,
+            Err(err) => {
+                bail_with_diagnostic!(response, "failed to deserialize config", format!("{err:#}"));
+            }
+        };
+
+        let resource_state =
+            match compute_resource_state::<R>(request.prior_state, request.config, None) {
+                Ok(resource_state) => resource_state,
+                Err(err) => {
+                    bail_with_diagnostic!(
+                        response,
+                        "failed to compute resource state",
+                        format!("{err:#}")
+                    );
+                }
+            };
+
+        let requires_replace = resource_state.requires_replace.clone();
+
+        let planned_state = match (resource_state.action, resource_state.did_change) {
+            (ResourceAction::Delete, _) => {
+                response.planned_state = Value::Null.to_msgpack().into_dynamic_value().into();
+                return Ok(Response::new(response));
+            }
+            (ResourceAction::Update, false) => {
+                let Some(prior_state) = resource_state.prior_state else {
+                    bail_with_diagnostic!(response, "prior state is missing");
+                };
+                prior_state
+            }
+            (ResourceAction::Update, true) => {
+                let (Some(prior_state), Some(config)) =
+                    (resource_state.prior_state, resource_state.config)
+                else {
+                    bail_with_diagnostic!(response, "prior state is missing");
+                };
+
+                self.plan_update(prior_state, config)
+            }
+            (ResourceAction::Create | ResourceAction::Replace, _) => {
+                let Some(config) = resource_state.config else {
+                    bail_with_diagnostic!(response, "config is missing");
+                };
+
+                self.plan_create(config)
+            }
+        };
+
+        info!("planned_state: {:?}", Redacted(&planned_state));
+        info!("requires_replace: {:?}", requires_replace);
+
+        let mut planned_state = match cty::to_value(&planned_state) {
+            Ok(planned_state) => planned_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to serialize planned state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        // the model only holds placeholders for config values that are not known yet
+        planned_state.mark_unknowns_from(&config);
+
+        let planned_state = match planned_state.conform(&R::State::block().cty_type()) {
+            Ok(planned_state) => planned_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "planned state doesn't match the schema",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        Ok(Response::new(tf::plan_resource_change::Response {
+            planned_state: planned_state.to_msgpack().into_dynamic_value().into(),
+            requires_replace: requires_replace
+                .into_iter()
+                .map(|name| tf::AttributePath {
+                    steps: vec![tf::attribute_path::Step {
+                        selector: Some(tf::attribute_path::step::Selector::AttributeName(
+                            name.into(),
+                        )),
+                    }],
+                })
+                .collect(),
+            planned_private: vec![],
+            diagnostics: vec![],
+        }))
+    }
+
+    async fn apply(
+        &self,
+        request: tf::apply_resource_change::Request,
+    ) -> tonic::Result<Response<tf::apply_resource_change::Response>> {
+        let mut response = tf::apply_resource_change::Response::default();
+
+        let resource_state = match compute_resource_state::<R>(
+            request.prior_state,
+            request.config,
+            Some(request.planned_state.unwrap_or_default()),
+        ) {
+            Ok(resource_state) => resource_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to compute resource state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        let mut diagnostics = Diagnostics::default();
+
+        let new_state = match (
+            resource_state.action,
+            resource_state.prior_state,
+            resource_state.planned_state,
+        ) {
+            (ResourceAction::Delete, Some(prior_state), _) => {
+                info!("deleting resource");
+
+                self.delete(prior_state, &mut diagnostics)
+                    .await
+                    .map(|_| None)
+            }
+            (ResourceAction::Update, Some(prior_state), Some(planned_state)) => {
+                info!("updating resource");
+
+                self.update(prior_state, planned_state, &mut diagnostics)
+                    .await
+                    .map(Some)
+            }
+            (ResourceAction::Create, _, Some(planned_state)) => {
+                info!("creating resource");
+
+                self.create(planned_state, &mut diagnostics).await.map(Some)
+            }
+            (ResourceAction::Replace, _, _) => {
+                bail_with_diagnostic!(
+                    response,
+                    "unexpected replacement",
+                    format!(
+                        "Terraform applies a replacement as a delete followed by a create, but was asked to change {} in place",
+                        resource_state.requires_replace.join(", ")
+                    )
+                );
+            }
+            _ => {
+                bail_with_diagnostic!(response, "planned state is missing");
+            }
+        };
+
+        response.diagnostics = diagnostics.diagnostics;
+
+        let new_state = match new_state {
+            Ok(new_state) => new_state,
+            Err(err) => {
+                bail_with_diagnostic!(response, err, format!("{err:#}"));
+            }
+        };
+
+        info!("new_state: {:?}", Redacted(&new_state));
+
+        // a deleted resource is reported back as a null state
+        let Some(new_state) = new_state else {
+            return Ok(Response::new(response));
+        };
+
+        let new_state = match serialize_dynamic_value(&new_state) {
+            Ok(new_state) => new_state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to serialize new state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        response.new_state = new_state.into_dynamic_value().into();
+        Ok(Response::new(response))
+    }
+
+    async fn import(
+        &self,
+        request: tf::import_resource_state::Request,
+    ) -> tonic::Result<Response<tf::import_resource_state::Response>> {
+        let mut response = tf::import_resource_state::Response::default();
+
+        let state = match Resource::import(self, &request.id).await {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(response, err, format!("{err:#}"));
+            }
+        };
+
+        info!("imported_state: {:?}", Redacted(&state));
+
+        let state = match serialize_dynamic_value(&state) {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to serialize imported state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        response
+            .imported_resources
+            .push(tf::import_resource_state::ImportedResource {
+                type_name: request.type_name,
+                state: state.into_dynamic_value().into(),
+                private: vec![],
+            });
+
+        Ok(Response::new(response))
+    }
+
+    async fn upgrade(
+        &self,
+        request: tf::upgrade_resource_state::Request,
+    ) -> tonic::Result<Response<tf::upgrade_resource_state::Response>> {
+        let mut response = tf::upgrade_resource_state::Response::default();
+
+        // state is always stored as JSON, which decodes like any other dynamic value
+        let raw_state = tf::DynamicValue {
+            msgpack: vec![],
+            json: request.raw_state.unwrap_or_default().json,
+        };
+
+        let state = match deserialize_dynamic_value::<R::State>(raw_state) {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to deserialize raw state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        register_secrets(&state);
+        info!("state: {:?}", Redacted(&state));
+
+        let state = match serialize_dynamic_value(&state) {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to serialize upgraded state",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        response.upgraded_state = state.into_dynamic_value().into();
+        Ok(Response::new(response))
+    }
+}
+
+/// Object safe side of [`DataSource`].
+#[tonic::async_trait]
+pub trait AnyDataSource: Send + Sync {
+    fn schema(&self) -> tf::Schema;
+
+    async fn read(
+        &self,
+        request: tf::read_data_source::Request,
+    ) -> tonic::Result<Response<tf::read_data_source::Response>>;
+}
+
+#[tonic::async_trait]
+impl<D: DataSource> AnyDataSource for D {
+    fn schema(&self) -> tf::Schema {
+        D::State::schema()
+    }
+
+    async fn read(
+        &self,
+        request: tf::read_data_source::Request,
+    ) -> tonic::Result<Response<tf::read_data_source::Response>> {
+        let mut response = tf::read_data_source::Response::default();
+
+        let config = request.config.unwrap_or_default();
+
+        let config = match deserialize_dynamic_value::<D::Config>(config) {
+            Ok(config) => config,
+            Err(err) => {
+                bail_with_diagnostic!(
+                    response,
+                    "failed to deserialize configuration",
+                    format!("{err:#}")
+                );
+            }
+        };
+
+        let state = match DataSource::read(self, config).await {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(response, err, format!("{err:#}"));
+            }
+        };
+
+        info!("state: {:?}", Redacted(&state));
+
+        let state = match serialize_dynamic_value(&state) {
+            Ok(state) => state,
+            Err(err) => {
+                bail_with_diagnostic!(response, "failed to serialize state", format!("{err:#}"));
+            }
+        };
+
+        response.state = state.into_dynamic_value().into();
+        Ok(Response::new(response))
+    }
+}
+
+/// Resource and data source types offered by a provider, keyed by their type name.
+#[derive(Default)]
+pub struct Registry {
+    resources: HashMap<&'static str, Box<dyn AnyResource>>,
+    data_sources: HashMap<&'static str, Box<dyn AnyDataSource>>,
+}
+
+impl Registry {
+    pub fn resource<R: Resource>(mut self, type_name: &'static str, resource: R) -> Self {
+        self.resources.insert(type_name, Box::new(resource));
+        self
+    }
+
+    pub fn data_source<D: DataSource>(mut self, type_name: &'static str, data_source: D) -> Self {
+        self.data_sources.insert(type_name, Box::new(data_source));
+        self
+    }
+
+    pub fn get_resource(&self, type_name: &str) -> Option<&dyn AnyResource> {
+        self.resources.get(type_name).map(AsRef::as_ref)
+    }
+
+    pub fn get_data_source(&self, type_name: &str) -> Option<&dyn AnyDataSource> {
+        self.data_sources.get(type_name).map(AsRef::as_ref)
+    }
+
+    pub fn resource_schemas(&self) -> HashMap<String, tf::Schema> {
+        self.resources
+            .iter()
+            .map(|(type_name, resource)| (type_name.to_string(), resource.schema()))
+            .collect()
+    }
+
+    pub fn data_source_schemas(&self) -> HashMap<String, tf::Schema> {
+        self.data_sources
+            .iter()
+            .map(|(type_name, data_source)| (type_name.to_string(), data_source.schema()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{cty::MaybeUnknown, model};
+
+    model! {
+        #[schema]
+        #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+        struct TestConfig {
+            #[schema(required)]
+            name: String,
+
+            #[schema(required, replace)]
+            size: String,
+        }
+    }
+
+    model! {
+        #[schema]
+        #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+        struct TestState {
+            #[schema(flatten)]
+            #[serde(flatten)]
+            config: TestConfig,
+
+            #[schema(computed)]
+            id: MaybeUnknown<String>,
+        }
+    }
+
+    struct TestResource;
+
+    #[tonic::async_trait]
+    impl Resource for TestResource {
+        type Config = TestConfig;
+        type State = TestState;
+
+        fn config(state: &TestState) -> &TestConfig {
+            &state.config
+        }
+
+        fn plan_create(&self, config: TestConfig) -> TestState {
+            TestState {
+                config,
+                id: MaybeUnknown::Unknown,
+            }
+        }
+
+        fn plan_update(&self, prior_state: TestState, config: TestConfig) -> TestState {
+            TestState {
+                config,
+                ..prior_state
+            }
+        }
+
+        async fn read(&self, state: TestState) -> Result<Option<TestState>> {
+            Ok(Some(state))
+        }
+
+        async fn create(
+            &self,
+            planned_state: TestState,
+            _diagnostics: &mut Diagnostics,
+        ) -> Result<TestState> {
+            Ok(planned_state)
+        }
+
+        async fn update(
+            &self,
+            _prior_state: TestState,
+            planned_state: TestState,
+            _diagnostics: &mut Diagnostics,
+        ) -> Result<TestState> {
+            Ok(planned_state)
+        }
+
+        async fn delete(
+            &self,
+            _prior_state: TestState,
+            _diagnostics: &mut Diagnostics,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn config(name: &str, size: &str) -> TestConfig {
+        TestConfig {
+            name: name.to_string(),
+            size: size.to_string(),
+        }
+    }
+
+    fn state(name: &str, size: &str) -> TestState {
+        TestState {
+            config: config(name, size),
+            id: MaybeUnknown::Known("1".to_string()),
+        }
+    }
+
+    fn dynamic_value<T: Model + Serialize>(value: Option<T>) -> Option<tf::DynamicValue> {
+        value.map(|value| {
+            serialize_dynamic_value(&value)
+                .unwrap()
+                .into_dynamic_value()
+        })
+    }
+
+    /// The outcome as `(action, did_change, requires_replace)`, or `None` for an error.
+    type Outcome = Option<(ResourceAction, bool, Vec<&'static str>)>;
+
+    fn outcome(result: Result<ResourceState<TestResource>>) -> Outcome {
+        result
+            .ok()
+            .map(|state| (state.action, state.did_change, state.requires_replace))
+    }
+
+    #[test]
+    fn computes_the_action_when_planning() {
+        let cases: [(&str, Option<TestState>, Option<TestConfig>, Outcome); 6] = [
+            ("nothing", None, None, None),
+            (
+                "create",
+                None,
+                Some(config("vm", "small")),
+                Some((ResourceAction::Create, true, vec![])),
+            ),
+            (
+                "delete",
+                Some(state("vm", "small")),
+                None,
+                Some((ResourceAction::Delete, true, vec![])),
+            ),
+            (
+                "no change",
+                Some(state("vm", "small")),
+                Some(config("vm", "small")),
+                Some((ResourceAction::Update, false, vec![])),
+            ),
+            (
+                "in-place change",
+                Some(state("vm", "small")),
+                Some(config("web", "small")),
+                Some((ResourceAction::Update, true, vec![])),
+            ),
+            (
+                "replace",
+                Some(state("vm", "small")),
+                Some(config("web", "large")),
+                Some((ResourceAction::Replace, true, vec!["size"])),
+            ),
+        ];
+
+        for (name, prior_state, config, expected) in cases {
+            let result = compute_resource_state::<TestResource>(
+                dynamic_value(prior_state),
+                dynamic_value(config),
+                None,
+            );
+
+            assert_eq!(outcome(result), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn computes_the_action_when_applying() {
+        // the planned state is always sent when applying, null when the resource is deleted
+        let cases: [(&str, Option<TestState>, Option<TestState>, Outcome); 6] = [
+            ("nothing", None, None, None),
+            (
+                "create",
+                None,
+                Some(state("vm", "small")),
+                Some((ResourceAction::Create, true, vec![])),
+            ),
+            (
+                "delete",
+                Some(state("vm", "small")),
+                None,
+                Some((ResourceAction::Delete, true, vec![])),
+            ),
+            (
+                "no change",
+                Some(state("vm", "small")),
+                Some(state("vm", "small")),
+                Some((ResourceAction::Update, false, vec![])),
+            ),
+            (
+                "in-place change",
+                Some(state("vm", "small")),
+                Some(state("web", "small")),
+                Some((ResourceAction::Update, true, vec![])),
+            ),
+            (
+                "replace",
+                Some(state("vm", "small")),
+                Some(state("vm", "large")),
+                Some((ResourceAction::Replace, true, vec!["size"])),
+            ),
+        ];
+
+        for (name, prior_state, planned_state, expected) in cases {
+            let config = planned_state.as_ref().map(|state| state.config.clone());
+            let planned_state = serialize_dynamic_value(&planned_state).unwrap();
+
+            let result = compute_resource_state::<TestResource>(
+                dynamic_value(prior_state),
+                dynamic_value(config),
+                Some(planned_state.into_dynamic_value()),
+            );
+
+            assert_eq!(outcome(result), expected, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_to_apply_a_replacement() {
+        let request = tf::apply_resource_change::Request {
+            prior_state: dynamic_value(Some(state("vm", "small"))),
+            planned_state: dynamic_value(Some(state("vm", "large"))),
+            config: dynamic_value(Some(config("vm", "large"))),
+            ..Default::default()
+        };
+
+        let response = AnyResource::apply(&TestResource, request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.new_state, None);
+        assert_eq!(response.diagnostics.len(), 1);
+        assert_eq!(response.diagnostics[0].summary, "unexpected replacement");
+    }
+
+    #[tokio::test]
+    async fn reports_why_the_resource_state_could_not_be_computed() {
+        let request = tf::plan_resource_change::Request {
+            prior_state: Some(tf::DynamicValue {
+                json: br#"{"name": "vm"}"#.to_vec(),
+                ..Default::default()
+            }),
+            config: dynamic_value(Some(config("vm", "small"))),
+            ..Default::default()
+        };
+
+        let response = AnyResource::plan(&TestResource, request)
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.diagnostics.len(), 1);
+        assert_eq!(
+            response.diagnostics[0].summary,
+            "failed to compute resource state"
+        );
+        assert!(response.diagnostics[0].detail.contains("missing field"));
+    }
+}
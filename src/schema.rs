@@ -0,0 +1,343 @@
+use crate::{
+    cty::{MaybeUnknown, Type},
+    server::tf,
+};
+
+/// Rust types that have a matching cty type.
+pub trait CtyType {
+    fn cty_type() -> Type;
+}
+
+impl CtyType for String {
+    fn cty_type() -> Type {
+        Type::String
+    }
+}
+
+impl CtyType for bool {
+    fn cty_type() -> Type {
+        Type::Bool
+    }
+}
+
+impl CtyType for i64 {
+    fn cty_type() -> Type {
+        Type::Number
+    }
+}
+
+impl CtyType for f64 {
+    fn cty_type() -> Type {
+        Type::Number
+    }
+}
+
+impl<T: CtyType> CtyType for Option<T> {
+    fn cty_type() -> Type {
+        T::cty_type()
+    }
+}
+
+impl<T: CtyType> CtyType for MaybeUnknown<T> {
+    fn cty_type() -> Type {
+        T::cty_type()
+    }
+}
+
+impl<T: CtyType> CtyType for Vec<T> {
+    fn cty_type() -> Type {
+        Type::list(T::cty_type())
+    }
+}
+
+pub struct Attribute {
+    name: &'static str,
+    ty: Type,
+    description: String,
+    markdown: bool,
+    required: bool,
+    optional: bool,
+    computed: bool,
+    sensitive: bool,
+    requires_replace: bool,
+}
+
+impl Attribute {
+    pub fn new<T: CtyType>(name: &'static str, description: &[&str]) -> Self {
+        Self {
+            name,
+            ty: T::cty_type(),
+            description: join_description(description),
+            markdown: false,
+            required: false,
+            optional: false,
+            computed: false,
+            sensitive: false,
+            requires_replace: false,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    pub fn computed(mut self) -> Self {
+        self.computed = true;
+        self
+    }
+
+    pub fn sensitive(mut self) -> Self {
+        self.sensitive = true;
+        self
+    }
+
+    pub fn markdown(mut self) -> Self {
+        self.markdown = true;
+        self
+    }
+
+    /// Changing the attribute forces the resource to be recreated.
+    pub fn replace(mut self) -> Self {
+        self.requires_replace = true;
+        self
+    }
+}
+
+impl From<Attribute> for tf::schema::Attribute {
+    fn from(attribute: Attribute) -> Self {
+        tf::schema::Attribute {
+            name: attribute.name.to_string(),
+            r#type: attribute.ty.encode(),
+            nested_type: None,
+            description: attribute.description,
+            required: attribute.required,
+            optional: attribute.optional,
+            computed: attribute.computed,
+            sensitive: attribute.sensitive,
+            description_kind: string_kind(attribute.markdown) as i32,
+            deprecated: false,
+        }
+    }
+}
+
+pub struct Block {
+    description: String,
+    attributes: Vec<Attribute>,
+    blocks: Vec<(&'static str, Block)>,
+}
+
+impl Block {
+    pub fn new(description: &[&str]) -> Self {
+        Self {
+            description: join_description(description),
+            attributes: vec![],
+            blocks: vec![],
+        }
+    }
+
+    pub fn attribute(&mut self, attribute: Attribute) {
+        self.attributes.push(attribute);
+    }
+
+    /// Adds a block that may appear at most once, like `timeouts { ... }`.
+    pub fn single_block(&mut self, name: &'static str, block: Block) {
+        self.blocks.push((name, block));
+    }
+
+    /// Merges the attributes and blocks of a `#[serde(flatten)]`-ed struct.
+    pub fn flatten(&mut self, block: Block) {
+        self.attributes.extend(block.attributes);
+        self.blocks.extend(block.blocks);
+    }
+
+    /// Names of the attributes that force the resource to be recreated when changed.
+    pub fn replace_attributes(&self) -> Vec<&'static str> {
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.requires_replace)
+            .map(|attribute| attribute.name)
+            .collect()
+    }
+
+    /// Names of the attributes whose values are kept out of logs and the CLI output.
+    pub fn sensitive_attributes(&self) -> Vec<&'static str> {
+        self.attributes
+            .iter()
+            .filter(|attribute| attribute.sensitive)
+            .map(|attribute| attribute.name)
+            .collect()
+    }
+
+    /// Nested blocks with their names.
+    pub fn blocks(&self) -> impl Iterator<Item = (&'static str, &Block)> {
+        self.blocks.iter().map(|(name, block)| (*name, block))
+    }
+
+    /// The object type a value of this block is encoded as.
+    pub fn cty_type(&self) -> Type {
+        Type::object(
+            self.attributes
+                .iter()
+                .map(|attribute| (attribute.name, attribute.ty.clone()))
+                .chain(
+                    self.blocks
+                        .iter()
+                        .map(|(name, block)| (*name, block.cty_type())),
+                ),
+        )
+    }
+}
+
+impl From<Block> for tf::schema::Block {
+    fn from(block: Block) -> Self {
+        tf::schema::Block {
+            version: 1,
+            attributes: block.attributes.into_iter().map(Into::into).collect(),
+            block_types: block
+                .blocks
+                .into_iter()
+                .map(|(name, block)| tf::schema::NestedBlock {
+                    type_name: name.to_string(),
+                    block: Some(block.into()),
+                    nesting: tf::schema::nested_block::NestingMode::Single as i32,
+                    min_items: 0,
+                    max_items: 0,
+                })
+                .collect(),
+            description: block.description,
+            description_kind: tf::StringKind::Plain as i32,
+            deprecated: false,
+        }
+    }
+}
+
+/// Structs that map to a schema block. Implemented through [`model!`](crate::model).
+pub trait Model {
+    fn block() -> Block;
+
+    fn schema() -> tf::Schema {
+        tf::Schema {
+            version: 1,
+            block: Some(Self::block().into()),
+        }
+    }
+}
+
+impl<T: Model> Model for Option<T> {
+    fn block() -> Block {
+        T::block()
+    }
+}
+
+/// Attribute level comparison of two values of a model.
+pub trait Diff {
+    fn changed_attributes(&self, other: &Self) -> Vec<&'static str>;
+}
+
+fn join_description(lines: &[&str]) -> String {
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn string_kind(markdown: bool) -> tf::StringKind {
+    if markdown {
+        tf::StringKind::Markdown
+    } else {
+        tf::StringKind::Plain
+    }
+}
+
+/// Declares a struct together with its schema, so the serde model and the schema sent to
+/// Terraform can't drift apart.
+///
+/// Doc comments become descriptions. The struct is marked with `#[schema]` after its doc
+/// comment, and every field takes a `#[schema(...)]` attribute after its own:
+/// `required`, `optional`, `computed`, `sensitive`, `markdown` and `replace` describe an
+/// attribute, `flatten` merges a nested model (use it with `#[serde(flatten)]`) and
+/// `block` declares a single nested block.
+#[macro_export]
+macro_rules! model {
+    (@block $block:ident, [flatten], $field:ident, $ty:ty, [$($doc:literal),*]) => {
+        $block.flatten(<$ty as $crate::schema::Model>::block());
+    };
+    (@block $block:ident, [block], $field:ident, $ty:ty, [$($doc:literal),*]) => {
+        $block.single_block(stringify!($field), <$ty as $crate::schema::Model>::block());
+    };
+    (@block $block:ident, [$($flag:ident)*], $field:ident, $ty:ty, [$($doc:literal),*]) => {
+        $block.attribute(
+            $crate::schema::Attribute::new::<$ty>(stringify!($field), &[$($doc),*])
+                $(.$flag())*
+        );
+    };
+
+    (@diff $changed:ident, $self:ident, $other:ident, [flatten], $field:ident) => {
+        $changed.extend($crate::schema::Diff::changed_attributes(&$self.$field, &$other.$field));
+    };
+    (@diff $changed:ident, $self:ident, $other:ident, [$($flag:ident)*], $field:ident) => {
+        if $self.$field != $other.$field {
+            $changed.push(stringify!($field));
+        }
+    };
+
+    (
+        $(#[doc = $doc:literal])*
+        #[schema]
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[doc = $field_doc:literal])*
+                #[schema($($flag:ident),*)]
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty,
+            )*
+        }
+    ) => {
+        $(#[doc = $doc])*
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[doc = $field_doc])*
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::schema::Model for $name {
+            fn block() -> $crate::schema::Block {
+                #[allow(unused_mut)]
+                let mut block = $crate::schema::Block::new(&[$($doc),*]);
+                $(
+                    $crate::model!(@block block, [$($flag)*], $field, $ty, [$($field_doc),*]);
+                )*
+                block
+            }
+        }
+
+        impl $crate::schema::CtyType for $name {
+            fn cty_type() -> $crate::cty::Type {
+                <$name as $crate::schema::Model>::block().cty_type()
+            }
+        }
+
+        impl $crate::schema::Diff for $name {
+            fn changed_attributes(&self, other: &Self) -> Vec<&'static str> {
+                #[allow(unused_mut)]
+                let mut changed = vec![];
+                $(
+                    $crate::model!(@diff changed, self, other, [$($flag)*], $field);
+                )*
+                changed
+            }
+        }
+    };
+}
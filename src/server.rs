@@ -1,283 +1,218 @@
-use std::{collections::HashMap, process::exit};
+use std::fmt::Debug;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::sync::CancellationToken;
+use tonic::{transport::Server, Request, Response, Result};
+use tracing::{debug, info, info_span, Instrument, Span};
 
 use crate::{
     bail_with_diagnostic,
-    ubicloud::{
-        Client as UbicloudClient, Credentials as UbicloudCredentials, VmCreateInput, VmState,
+    handshake::{
+        negotiate_from_env, negotiate_protocol_version, HandshakeLine, ReattachConfig,
+        SUPPORTED_PROTOCOL_VERSIONS,
     },
-    util::{
-        compute_resource_state, deserialize_dynamic_value, random_hex_suffix,
-        serialize_dynamic_value, IntoDynamicValue, ResourceAction, UNKNOWN_STRING,
+    listener::Listener,
+    logging::{register_secrets, Redacted},
+    plugin::{
+        self,
+        proto::{grpc_controller_server::GrpcControllerServer, grpc_stdio_server::GrpcStdioServer},
+        Controller, StdioService,
     },
+    resource::Registry,
+    schema::Model,
+    tls::{client_cert_from_env, generate_server_cert, serve_with_tls, serve_without_tls},
+    util::deserialize_dynamic_value,
 };
-use serde::{Deserialize, Serialize};
-use tonic::{Request, Response, Result};
-use tracing::info;
 
 pub mod tf {
+    #![allow(dead_code)]
+
     tonic::include_proto!("tfplugin6");
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ProviderConfig {
-    email: String,
-    password: String,
-}
+/// A Terraform provider, served with [`serve`].
+#[tonic::async_trait]
+pub trait Provider: Send + Sync + 'static {
+    /// The `provider { ... }` block.
+    type Config: Model + DeserializeOwned + Serialize + Debug + Send;
 
-#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Clone)]
-pub struct VmResourceConfig {
-    pub region: String,
-    pub project_id: String,
-    pub name: String,
-    pub size: String,
-    pub image: String,
-    pub user: String,
-    pub public_key: String,
-    pub enable_public_ipv4: Option<bool>,
-}
+    /// Full address of the provider, like `registry.terraform.io/ubicloud/ubicloud`.
+    fn address(&self) -> &'static str;
+
+    /// Resource and data source types offered by the provider.
+    fn registry(&self) -> &Registry;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct VmResourceState {
-    #[serde(flatten)]
-    pub config: VmResourceConfig,
+    /// Schema of the `provider_meta` block modules can set for the provider.
+    fn provider_meta(&self) -> Option<tf::Schema> {
+        None
+    }
 
-    pub vm_name: String,
-    pub public_ipv4: Option<String>,
-    pub public_ipv6: Option<String>,
+    /// Called with the provider configuration before any resource or data source is used.
+    async fn configure(&self, config: Self::Config) -> anyhow::Result<()>;
 }
 
-#[derive(Debug)]
-pub struct UbicloudProvider {
-    ubicloud: UbicloudClient,
+/// Implements the `tfplugin6.Provider` service for a [`Provider`], routing requests to
+/// its resources and data sources by type name.
+pub struct ProviderService<P> {
+    provider: P,
+    shutdown: Option<CancellationToken>,
 }
 
-impl UbicloudProvider {
-    pub fn new() -> Self {
+impl<P: Provider> ProviderService<P> {
+    /// `shutdown` is cancelled when Terraform asks the provider to stop.
+    pub fn new(provider: P, shutdown: CancellationToken) -> Self {
+        Self {
+            provider,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// A service that keeps running when Terraform asks the provider to stop, so it can
+    /// outlive the Terraform run it was attached to.
+    pub fn detached(provider: P) -> Self {
         Self {
-            ubicloud: UbicloudClient::new(None),
+            provider,
+            shutdown: None,
         }
     }
+
+    /// Span the handling of a provider wide RPC is logged in.
+    fn provider_span(&self, rpc: &'static str) -> Span {
+        info_span!(
+            "rpc",
+            tf_rpc = rpc,
+            tf_provider_addr = self.provider.address()
+        )
+    }
+
+    fn resource_span(&self, rpc: &'static str, type_name: &str) -> Span {
+        info_span!(
+            "rpc",
+            tf_rpc = rpc,
+            tf_provider_addr = self.provider.address(),
+            tf_resource_type = type_name
+        )
+    }
+
+    fn data_source_span(&self, rpc: &'static str, type_name: &str) -> Span {
+        info_span!(
+            "rpc",
+            tf_rpc = rpc,
+            tf_provider_addr = self.provider.address(),
+            tf_data_source_type = type_name
+        )
+    }
+}
+
+/// Serves `provider` to Terraform and prints the handshake line it waits for on stdout.
+/// Refuses to start when the binary was not launched by Terraform.
+///
+/// Returns once `shutdown` is cancelled, by the caller or by Terraform through
+/// `StopProvider` or the go-plugin controller, and open connections finished their
+/// in-flight requests. Fails when Terraform's client certificate is missing, see
+/// [`client_cert_from_env`].
+pub async fn serve<P: Provider>(provider: P, shutdown: CancellationToken) -> anyhow::Result<()> {
+    let protocol_version = negotiate_from_env()?;
+    let client_cert = client_cert_from_env()?;
+
+    let listener = Listener::bind_from_env().await?;
+
+    let (cert, key) = generate_server_cert()?;
+
+    let serve = Server::builder()
+        .add_service(tf::provider_server::ProviderServer::new(
+            ProviderService::new(provider, shutdown.clone()),
+        ))
+        .add_service(GrpcControllerServer::new(Controller::new(shutdown.clone())))
+        .add_service(GrpcStdioServer::new(StdioService))
+        .into_service();
+
+    // only announced once the listener is bound, so Terraform never sees an address
+    // nothing listens on
+    let handshake = HandshakeLine {
+        protocol_version,
+        network: listener.network(),
+        address: &listener.address()?,
+        server_cert: cert.as_ref(),
+    }
+    .to_string();
+
+    info!("provider listening: {handshake}");
+    println!("{handshake}");
+
+    plugin::forward_panics();
+
+    serve_with_tls(serve, cert, key, client_cert, listener, shutdown).await?;
+
+    Ok(())
+}
+
+/// Starts `provider` by hand for debugging, and prints the `TF_REATTACH_PROVIDERS` value
+/// that points Terraform at it instead of launching the provider itself.
+///
+/// The provider keeps running across Terraform runs, ignoring `StopProvider`, until
+/// `shutdown` is cancelled or it gets interrupted with Ctrl-C.
+pub async fn serve_debug<P: Provider>(
+    provider: P,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let protocol_version = negotiate_protocol_version(None, SUPPORTED_PROTOCOL_VERSIONS)?;
+    let provider_address = provider.address();
+
+    let listener = Listener::bind_from_env().await?;
+
+    let serve = Server::builder()
+        .add_service(tf::provider_server::ProviderServer::new(
+            ProviderService::detached(provider),
+        ))
+        .into_service();
+
+    let reattach = ReattachConfig {
+        provider_address,
+        protocol_version,
+        pid: std::process::id(),
+        network: listener.network(),
+        address: &listener.address()?,
+    };
+
+    info!("provider started in debug mode: {reattach}");
+    println!("Provider started. To attach Terraform, set TF_REATTACH_PROVIDERS:\n");
+    println!("\texport TF_REATTACH_PROVIDERS='{reattach}'");
+
+    let interrupted = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupted.cancel();
+        }
+    });
+
+    serve_without_tls(serve, listener, shutdown).await
 }
 
 #[tonic::async_trait]
-impl tf::provider_server::Provider for UbicloudProvider {
+impl<P: Provider> tf::provider_server::Provider for ProviderService<P> {
     async fn get_provider_schema(
         &self,
         request: Request<tf::get_provider_schema::Request>,
     ) -> Result<Response<tf::get_provider_schema::Response>> {
-        info!("get_provider_schema: {:?}", request);
+        let _span = self.provider_span("GetProviderSchema").entered();
+        debug!(?request, "received request");
 
         Ok(Response::new(tf::get_provider_schema::Response {
-            provider: Some(tf::Schema {
-                version: 1,
-                block: Some(tf::schema::Block {
-                    version: 1,
-                    attributes: vec![
-                        tf::schema::Attribute {
-                            name: "email".to_string(),
-                            r#type: "\"string\"".as_bytes().to_vec(),
-                            nested_type: None,
-                            description: "Email for Ubicloud account used to provision resources"
-                                .to_string(),
-                            description_kind: tf::StringKind::Plain as i32,
-                            required: true,
-                            optional: false,
-                            computed: false,
-                            sensitive: true,
-                            deprecated: false,
-                        },
-                        tf::schema::Attribute {
-                            name: "password".to_string(),
-                            r#type: "\"string\"".as_bytes().to_vec(),
-                            nested_type: None,
-                            description:
-                                "Password for Ubicloud account used to provision resources"
-                                    .to_string(),
-                            description_kind: tf::StringKind::Plain as i32,
-                            required: true,
-                            optional: false,
-                            computed: false,
-                            sensitive: true,
-                            deprecated: false,
-                        },
-                    ],
-                    block_types: vec![],
-                    description: "Ubicloud provider".to_string(),
-                    description_kind: tf::StringKind::Plain as i32,
-                    deprecated: false,
-                }),
-            }),
-            resource_schemas: [(
-                "ubicloud_vm".to_string(),
-                tf::Schema {
-                    version: 1,
-                    block: Some(tf::schema::Block {
-                        version: 1,
-                        attributes: vec![
-                            tf::schema::Attribute {
-                                name: "region".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Region where the VM will be created in. Current supported options are `hetzner-hel1` or `hetzner-fsn1`.".to_string(),
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                description_kind: tf::StringKind::Markdown as i32,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "project_id".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Project where the VM will be created in.".to_string(),
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                description_kind: tf::StringKind::Markdown as i32,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "name".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Friendly name of the resource (used to compute the real name).".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "size".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Size fo the VM. Current supported options are `standard-2`, `standard-4`, `standard-8` and `standard-16`.".to_string(),
-                                description_kind: tf::StringKind::Markdown as i32,
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "image".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Image to use for the VM. Current supported options are `ubuntu-jammy` and `almalinux-9.1`.".to_string(),
-                                description_kind: tf::StringKind::Markdown as i32,
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "user".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Linux user used when creating the VM.".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "public_key".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "SSH public key used when creating the VM.".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: true,
-                                optional: false,
-                                computed: false,
-                                sensitive: true,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "enable_public_ipv4".to_string(),
-                                r#type: String::into_bytes("\"bool\"".to_string()),
-                                description: "Whether to enable public IPv4 for the VM. Defaults to `true`.".to_string(),
-                                description_kind: tf::StringKind::Markdown as i32,
-                                nested_type: None,
-                                required: false,
-                                optional: true,
-                                computed: false,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "vm_name".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "The real name of the VM in Ubicloud.".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: false,
-                                optional: true,
-                                computed: true,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "public_ipv4".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Public IPv4 address of the VM.".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: false,
-                                optional: true,
-                                computed: true,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                            tf::schema::Attribute {
-                                name: "public_ipv6".to_string(),
-                                r#type: String::into_bytes("\"string\"".to_string()),
-                                description: "Public IPv6 address of the VM.".to_string(),
-                                description_kind: tf::StringKind::Plain as i32,
-                                nested_type: None,
-                                required: false,
-                                optional: true,
-                                computed: true,
-                                sensitive: false,
-                                deprecated: false,
-                            },
-                        ],
-                        block_types: vec![],
-                        description: "Ubicloud Virtual Machine".to_string(),
-                        description_kind: tf::StringKind::Plain as i32,
-                        deprecated: false,
-                    }),
-                },
-            )]
-            .iter()
-            .cloned()
-            .collect(),
-            data_source_schemas: HashMap::new(),
+            provider: Some(P::Config::schema()),
+            resource_schemas: self.provider.registry().resource_schemas(),
+            data_source_schemas: self.provider.registry().data_source_schemas(),
             diagnostics: vec![],
-            provider_meta: Some(tf::Schema {
-                version: 1,
-                block: Some(tf::schema::Block {
-                    version: 1,
-                    attributes: vec![],
-                    block_types: vec![],
-                    description: "Ubicloud terraform provider".to_string(),
-                    description_kind: tf::StringKind::Markdown as i32,
-                    deprecated: false,
-                }),
-            }),
+            provider_meta: self.provider.provider_meta(),
         }))
     }
 
     async fn validate_provider_config(
         &self,
-        request: Request<tf::validate_provider_config::Request>,
+        _request: Request<tf::validate_provider_config::Request>,
     ) -> Result<Response<tf::validate_provider_config::Response>> {
-        info!("validate_provider_config: {:?}", request);
+        let _span = self.provider_span("ValidateProviderConfig").entered();
+        debug!("received request");
 
         Ok(Response::new(tf::validate_provider_config::Response {
             diagnostics: vec![],
@@ -288,43 +223,70 @@ impl tf::provider_server::Provider for UbicloudProvider {
         &self,
         request: Request<tf::configure_provider::Request>,
     ) -> Result<Response<tf::configure_provider::Response>> {
-        let mut response = tf::configure_provider::Response::default();
-
-        info!("configure_provider: {:?}", request);
-
-        let config: Vec<u8> = request.into_inner().config.unwrap().msgpack;
-        let Ok(config) = deserialize_dynamic_value::<ProviderConfig>(config) else {
-            bail_with_diagnostic!(response, "failed to deserialize configuration");
-        };
+        async {
+            let mut response = tf::configure_provider::Response::default();
+
+            debug!("received request");
+
+            let config = request.into_inner().config.unwrap_or_default();
+            let config = match deserialize_dynamic_value::<P::Config>(config) {
+                Ok(config) => config,
+                Err(err) => {
+                    bail_with_diagnostic!(
+                        response,
+                        "failed to deserialize configuration",
+                        format!("{err:#}")
+                    );
+                }
+            };
 
-        info!("config: {:?}", config);
+            register_secrets(&config);
+            info!("config: {:?}", Redacted(&config));
 
-        self.ubicloud
-            .set_credentials(UbicloudCredentials {
-                email: config.email,
-                password: config.password,
-            })
-            .await;
+            if let Err(err) = self.provider.configure(config).await {
+                bail_with_diagnostic!(response, err, format!("{err:#}"));
+            }
 
-        Ok(Response::new(response))
+            Ok(Response::new(response))
+        }
+        .instrument(self.provider_span("ConfigureProvider"))
+        .await
     }
 
     async fn validate_resource_config(
         &self,
         request: Request<tf::validate_resource_config::Request>,
     ) -> Result<Response<tf::validate_resource_config::Response>> {
-        info!("validate_resource_config: {:?}", request);
+        let request = request.into_inner();
+        let span = self.resource_span("ValidateResourceConfig", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::validate_resource_config::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
+            };
 
-        Ok(Response::new(tf::validate_resource_config::Response {
-            diagnostics: vec![],
-        }))
+            resource.validate(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn validate_data_resource_config(
         &self,
         request: Request<tf::validate_data_resource_config::Request>,
     ) -> Result<Response<tf::validate_data_resource_config::Response>> {
-        info!("validate_data_resource_config: {:?}", request);
+        let request = request.into_inner();
+        let _span = self
+            .data_source_span("ValidateDataResourceConfig", &request.type_name)
+            .entered();
+        debug!("received request");
 
         Ok(Response::new(tf::validate_data_resource_config::Response {
             diagnostics: vec![],
@@ -335,262 +297,167 @@ impl tf::provider_server::Provider for UbicloudProvider {
         &self,
         request: Request<tf::read_resource::Request>,
     ) -> Result<Response<tf::read_resource::Response>> {
-        info!("read_resource: {:?}", request);
-
-        let state = request.get_ref().clone().current_state.unwrap().msgpack;
+        let request = request.into_inner();
+        let span = self.resource_span("ReadResource", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::read_resource::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
+            };
 
-        Ok(Response::new(tf::read_resource::Response {
-            new_state: state.into_dynamic_value().into(),
-            private: vec![],
-            diagnostics: vec![],
-        }))
+            resource.read(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn plan_resource_change(
         &self,
         request: Request<tf::plan_resource_change::Request>,
     ) -> Result<Response<tf::plan_resource_change::Response>> {
-        let mut response = tf::plan_resource_change::Response::default();
-
-        info!("plan_resource_change: {:?}", request);
-
-        let Ok(resource_state) = compute_resource_state(
-            request.get_ref().clone().prior_state,
-            request.get_ref().clone().config,
-        ) else {
-            bail_with_diagnostic!(response, "failed to compute resource state");
-        };
-
-        let planned_state = if !resource_state.did_change {
-            let Some(prior_state) = resource_state.prior_state else {
-                bail_with_diagnostic!(response, "prior state is missing");
-            };
-            prior_state
-        } else {
-            let Some(config) = resource_state.config else {
-                bail_with_diagnostic!(response, "prior state is missing");
+        let request = request.into_inner();
+        let span = self.resource_span("PlanResourceChange", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::plan_resource_change::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
             };
 
-            VmResourceState {
-                config,
-                vm_name: UNKNOWN_STRING.to_owned().into(),
-                public_ipv4: UNKNOWN_STRING.to_owned().into(),
-                public_ipv6: UNKNOWN_STRING.to_owned().into(),
-            }
-        };
-
-        info!("planned_state: {:?}", planned_state);
-
-        let Ok(planned_state) = serialize_dynamic_value(&planned_state) else {
-            bail_with_diagnostic!(response, "failed to serialize planned state");
-        };
-
-        Ok(Response::new(tf::plan_resource_change::Response {
-            planned_state: planned_state.into_dynamic_value().into(),
-            requires_replace: vec![
-                "name",
-                "region",
-                "size",
-                "image",
-                "user",
-                "public_key",
-                "enable_public_ipv4",
-            ]
-            .into_iter()
-            .map(|name| tf::AttributePath {
-                steps: vec![tf::attribute_path::Step {
-                    selector: Some(tf::attribute_path::step::Selector::AttributeName(
-                        name.into(),
-                    )),
-                }],
-            })
-            .collect(),
-            planned_private: vec![],
-            diagnostics: vec![],
-        }))
+            resource.plan(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn apply_resource_change(
         &self,
         request: Request<tf::apply_resource_change::Request>,
     ) -> Result<Response<tf::apply_resource_change::Response>> {
-        let mut response = tf::apply_resource_change::Response::default();
-
-        info!("apply_resource_change: {:?}", request);
-
-        let Ok(resource_state) = compute_resource_state(
-            request.get_ref().clone().prior_state,
-            request.get_ref().clone().config,
-        ) else {
-            bail_with_diagnostic!(response, "failed to compute resource state");
-        };
-
-        if let ResourceAction::Delete = resource_state.action {
-            info!("deleting resource");
-
-            let Some(prior_state) = resource_state.prior_state else {
-                bail_with_diagnostic!(response, "prior state is missing");
+        let request = request.into_inner();
+        let span = self.resource_span("ApplyResourceChange", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::apply_resource_change::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
             };
 
-            let config = prior_state.config.clone();
-            let vm_name = prior_state.vm_name.clone();
-
-            let Ok(_) = self
-                .ubicloud
-                .delete_vm(config.project_id, config.region, vm_name)
-                .await
-            else {
-                bail_with_diagnostic!(response, "failed to delete vm");
-            };
-
-            loop {
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-
-                let config: VmResourceConfig = prior_state.config.clone();
-                let vm_name = prior_state.vm_name.clone();
-
-                let Ok(Some(_)) = self
-                    .ubicloud
-                    .get_vm(config.project_id, config.region, vm_name)
-                    .await
-                else {
-                    break;
-                };
-            }
-
-            return Ok(Response::new(tf::apply_resource_change::Response {
-                new_state: None,
-                private: vec![],
-                diagnostics: vec![],
-            }));
-        };
-
-        let planned_state_bytes = request
-            .get_ref()
-            .clone()
-            .planned_state
-            .unwrap_or_default()
-            .msgpack;
-        if planned_state_bytes.len() == 0 {
-            bail_with_diagnostic!(response, "planned state is missing");
-        };
-
-        let Ok(planned_state) = deserialize_dynamic_value::<VmResourceState>(planned_state_bytes)
-        else {
-            bail_with_diagnostic!(response, "planned state is missing");
-        };
-
-        let config = planned_state.config.clone();
-        let vm_name = format!("{}-{}", config.name, random_hex_suffix(3));
-
-        let Ok(_) = self
-            .ubicloud
-            .create_vm(
-                config.project_id,
-                config.region,
-                VmCreateInput {
-                    name: vm_name.clone(),
-                    size: config.size,
-                    image: config.image,
-                    user: config.user,
-                    public_key: config.public_key,
-                    enable_public_ipv4: config.enable_public_ipv4.unwrap_or(false),
-                },
-            )
-            .await
-        else {
-            bail_with_diagnostic!(response, "failed to create vm");
-        };
-
-        loop {
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-
-            let config = planned_state.config.clone();
-
-            let Ok(Some(vm)) = self
-                .ubicloud
-                .get_vm(config.project_id, config.region, vm_name.clone())
-                .await
-            else {
-                bail_with_diagnostic!(response, "failed to get vm");
-            };
-
-            match vm.state {
-                VmState::Running => break,
-                _ => continue,
-            };
+            resource.apply(request).await
         }
-
-        let config = planned_state.config.clone();
-
-        let Ok(Some(vm)) = self
-            .ubicloud
-            .get_vm(config.project_id, config.region, vm_name.clone())
-            .await
-        else {
-            bail_with_diagnostic!(response, "failed to get vm");
-        };
-
-        let mut new_state = planned_state.clone();
-        new_state.vm_name = vm.name;
-        new_state.public_ipv4 = vm.ip4;
-        new_state.public_ipv6 = vm.ip6;
-
-        info!("new_state: {:?}", new_state);
-
-        let Ok(new_state) = serialize_dynamic_value(&new_state) else {
-            bail_with_diagnostic!(response, "failed to serialize new state");
-        };
-
-        Ok(Response::new(tf::apply_resource_change::Response {
-            new_state: new_state.into_dynamic_value().into(),
-            private: vec![],
-            diagnostics: vec![],
-        }))
+        .instrument(span)
+        .await
     }
 
     async fn import_resource_state(
         &self,
         request: Request<tf::import_resource_state::Request>,
     ) -> Result<Response<tf::import_resource_state::Response>> {
-        info!("import_resource_state: {:?}", request);
-        todo!()
+        let request = request.into_inner();
+        let span = self.resource_span("ImportResourceState", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::import_resource_state::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
+            };
+
+            resource.import(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn upgrade_resource_state(
         &self,
         request: Request<tf::upgrade_resource_state::Request>,
     ) -> Result<Response<tf::upgrade_resource_state::Response>> {
-        info!("upgrade_resource_state: {:?}", request);
-        let raw_state = request.get_ref().raw_state.clone().unwrap();
-        let json = String::from_utf8_lossy(raw_state.json.as_slice());
-        info!("json: {:?}", json);
-
-        let state = serde_json::from_str::<VmResourceState>(&json).ok();
-        info!("state: {:?}", state);
-
-        Ok(Response::new(tf::upgrade_resource_state::Response {
-            upgraded_state: Some(tf::DynamicValue {
-                msgpack: rmp_serde::to_vec::<VmResourceState>(&state.unwrap()).unwrap(),
-                json: vec![],
-            }),
-            diagnostics: vec![],
-        }))
+        let request = request.into_inner();
+        let span = self.resource_span("UpgradeResourceState", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(resource) = self.provider.registry().get_resource(&request.type_name) else {
+                let mut response = tf::upgrade_resource_state::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown resource",
+                    format!("resource type `{}` is not supported", request.type_name)
+                );
+            };
+
+            resource.upgrade(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn read_data_source(
         &self,
         request: Request<tf::read_data_source::Request>,
     ) -> Result<Response<tf::read_data_source::Response>> {
-        info!("read_data_source: {:?}", request);
-        todo!()
+        let request = request.into_inner();
+        let span = self.data_source_span("ReadDataSource", &request.type_name);
+
+        async {
+            debug!("received request");
+
+            let Some(data_source) = self.provider.registry().get_data_source(&request.type_name)
+            else {
+                let mut response = tf::read_data_source::Response::default();
+                bail_with_diagnostic!(
+                    response,
+                    "unknown data source",
+                    format!("data source type `{}` is not supported", request.type_name)
+                );
+            };
+
+            data_source.read(request).await
+        }
+        .instrument(span)
+        .await
     }
 
     async fn stop_provider(
         &self,
         request: Request<tf::stop_provider::Request>,
     ) -> Result<Response<tf::stop_provider::Response>> {
-        info!("stop_provider: {:?}", request);
-        exit(0);
+        let _span = self.provider_span("StopProvider").entered();
+        debug!(?request, "received request");
+
+        // in-flight operations notice this, hand back what they have and the server
+        // drains its connections before shutting down
+        match &self.shutdown {
+            Some(shutdown) => shutdown.cancel(),
+            None => info!("provider is detached, keeping it running"),
+        }
+
+        Ok(Response::new(tf::stop_provider::Response::default()))
     }
 }
@@ -1,14 +1,28 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use hyper::server::conn::Http;
 use rcgen::{BasicConstraints, IsCa};
-use tokio::net::TcpListener;
+use tokio::{
+    sync::Semaphore,
+    task::{JoinError, JoinSet},
+    time::{timeout, Instant},
+};
 use tokio_rustls::{
-    rustls::{Certificate, PrivateKey, ServerConfig},
+    rustls::{
+        server::{ClientCertVerified, ClientCertVerifier},
+        Certificate, CertificateError, DistinguishedName, PrivateKey, RootCertStore, ServerConfig,
+    },
     TlsAcceptor,
 };
+use tokio_util::sync::CancellationToken;
 use tonic::transport::server::Routes;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::listener::{Connection, Listener};
 
 pub fn generate_server_cert() -> Result<(Certificate, PrivateKey)> {
     let mut cp = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
@@ -17,13 +31,11 @@ pub fn generate_server_cert() -> Result<(Certificate, PrivateKey)> {
 
     let cert = server_cert.serialize_pem()?;
     let certs: Vec<Certificate> = rustls_pemfile::certs(&mut cert.as_bytes())
-        .into_iter()
         .map(|x| Certificate(x.unwrap().as_ref().to_vec()))
         .collect();
 
     let key: String = server_cert.serialize_private_key_pem();
     let key: PrivateKey = rustls_pemfile::pkcs8_private_keys(&mut key.as_bytes())
-        .into_iter()
         .map(|x| PrivateKey(x.unwrap().secret_pkcs8_der().to_vec()))
         .next()
         .unwrap();
@@ -31,43 +43,494 @@ pub fn generate_server_cert() -> Result<(Certificate, PrivateKey)> {
     Ok((certs[0].clone(), key))
 }
 
+const CLIENT_CERT_ENV: &str = "PLUGIN_CLIENT_CERT";
+
+/// Reads the certificate Terraform authenticates with, which it passes to the plugins it
+/// starts as PEM in `PLUGIN_CLIENT_CERT`.
+pub fn client_cert_from_env() -> Result<Certificate> {
+    let Ok(pem) = std::env::var(CLIENT_CERT_ENV) else {
+        bail!(
+            "{CLIENT_CERT_ENV} is not set. This binary is a Terraform plugin and has to be started by Terraform"
+        );
+    };
+
+    let cert = rustls_pemfile::certs(&mut pem.as_bytes())
+        .next()
+        .ok_or_else(|| anyhow!("{CLIENT_CERT_ENV} does not contain a certificate"))?
+        .with_context(|| format!("{CLIENT_CERT_ENV} is not a valid PEM certificate"))?;
+
+    Ok(Certificate(cert.as_ref().to_vec()))
+}
+
+/// Only accepts clients presenting exactly the certificate Terraform handed to the plugin.
+///
+/// Terraform's certificate is self-signed and marked as a CA, which webpki refuses as an
+/// end-entity certificate, so it is pinned instead of verified against a root store.
+/// Possession of its private key is still checked through the handshake signature.
+struct PinnedClientCert {
+    certificate: Certificate,
+    subjects: Vec<DistinguishedName>,
+}
+
+impl PinnedClientCert {
+    fn new(certificate: Certificate) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(&certificate)
+            .context("failed to parse the client certificate")?;
+
+        let subjects = roots
+            .roots
+            .iter()
+            .map(|root| root.subject().clone())
+            .collect();
+
+        Ok(Self {
+            certificate,
+            subjects,
+        })
+    }
+}
+
+impl ClientCertVerifier for PinnedClientCert {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &self.subjects
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: SystemTime,
+    ) -> Result<ClientCertVerified, tokio_rustls::rustls::Error> {
+        if end_entity != &self.certificate {
+            return Err(CertificateError::UnknownIssuer.into());
+        }
+
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Serves `svc` to clients authenticating with `client_certificate` until `shutdown` is
+/// cancelled, then gives open connections a bounded time to finish their in-flight requests.
 pub async fn serve_with_tls(
     svc: Routes,
     certificate: Certificate,
     key: PrivateKey,
-    addr: &str,
+    client_certificate: Certificate,
+    listener: Listener,
+    shutdown: CancellationToken,
 ) -> Result<()> {
+    let tls_acceptor = tls_acceptor(certificate, key, client_certificate)?;
+
+    serve_connections(svc, listener, Some(tls_acceptor), shutdown, LIMITS).await
+}
+
+fn tls_acceptor(
+    certificate: Certificate,
+    key: PrivateKey,
+    client_certificate: Certificate,
+) -> Result<TlsAcceptor> {
     let mut tls = ServerConfig::builder()
         .with_safe_defaults()
-        .with_no_client_auth()
+        .with_client_cert_verifier(Arc::new(PinnedClientCert::new(client_certificate)?))
         .with_single_cert(vec![certificate], key)?;
     tls.alpn_protocols = vec![b"h2".to_vec()];
 
+    Ok(TlsAcceptor::from(Arc::new(tls)))
+}
+
+/// Serves `svc` over plaintext HTTP/2, for Terraform to reattach to a provider started by
+/// hand, which it does without TLS.
+pub async fn serve_without_tls(
+    svc: Routes,
+    listener: Listener,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    serve_connections(svc, listener, None, shutdown, LIMITS).await
+}
+
+/// Bounds that keep misbehaving clients from tying up the server.
+#[derive(Clone, Copy)]
+struct Limits {
+    /// Connections served at the same time. Terraform opens a single one per provider
+    /// process, further clients wait until a slot frees up.
+    max_connections: usize,
+
+    /// How long a client gets to complete the TLS handshake.
+    handshake_timeout: Duration,
+
+    /// How long open connections get to finish their requests on shutdown.
+    drain_timeout: Duration,
+}
+
+const LIMITS: Limits = Limits {
+    max_connections: 16,
+    handshake_timeout: Duration::from_secs(10),
+    drain_timeout: Duration::from_secs(30),
+};
+
+/// Pause after a failed accept, which usually means the process ran out of file
+/// descriptors and retrying right away would spin.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+async fn serve_connections(
+    svc: Routes,
+    listener: Listener,
+    tls_acceptor: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
+    limits: Limits,
+) -> Result<()> {
     let mut http = Http::new();
     http.http2_only(true);
 
-    let listener = TcpListener::bind(addr).await?;
-    let tls_acceptor = TlsAcceptor::from(Arc::new(tls));
+    let slots = Arc::new(Semaphore::new(limits.max_connections));
+    let mut connections = JoinSet::new();
+    let mut next_id: u64 = 0;
 
     loop {
-        let (conn, _addr) = match listener.accept().await {
-            Ok(incoming) => incoming,
-            Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
+        let slot = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            Some(result) = connections.join_next(), if !connections.is_empty() => {
+                log_connection_task(result);
                 continue;
             }
+            slot = slots.clone().acquire_owned() => slot?,
         };
 
+        let incoming = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            incoming = listener.accept() => incoming,
+        };
+
+        let conn = match incoming {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!(%err, "failed to accept connection");
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+
+        next_id += 1;
+        let span = info_span!(
+            "connection",
+            id = next_id,
+            active = limits.max_connections - slots.available_permits()
+        );
+
         let http = http.clone();
         let tls_acceptor = tls_acceptor.clone();
         let svc = svc.clone();
+        let shutdown = shutdown.clone();
+
+        connections.spawn(
+            async move {
+                serve_connection(conn, http, svc, tls_acceptor, shutdown, limits).await;
+                drop(slot);
+            }
+            .instrument(span),
+        );
+    }
+
+    info!(connections = connections.len(), "draining connections");
+
+    while let Some(result) = connections.join_next().await {
+        log_connection_task(result);
+    }
+
+    Ok(())
+}
+
+async fn serve_connection(
+    conn: Box<dyn Connection>,
+    http: Http,
+    svc: Routes,
+    tls_acceptor: Option<TlsAcceptor>,
+    shutdown: CancellationToken,
+    limits: Limits,
+) {
+    let opened_at = Instant::now();
+    info!("connection opened");
+
+    let conn: Box<dyn Connection> = match tls_acceptor {
+        Some(tls_acceptor) => {
+            let handshake = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                handshake = timeout(limits.handshake_timeout, tls_acceptor.accept(conn)) => handshake,
+            };
+
+            match handshake {
+                Ok(Ok(conn)) => Box::new(conn),
+                Ok(Err(err)) => {
+                    warn!(%err, "tls handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    warn!(timeout = ?limits.handshake_timeout, "tls handshake timed out");
+                    return;
+                }
+            }
+        }
+        None => conn,
+    };
+
+    let svc = tower::ServiceBuilder::new().service(svc);
+
+    let connection = http.serve_connection(conn, svc);
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.cancelled() => {
+            connection.as_mut().graceful_shutdown();
+
+            match timeout(limits.drain_timeout, connection).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!(timeout = ?limits.drain_timeout, "connection did not drain in time");
+                    return;
+                }
+            }
+        }
+    };
+
+    match result {
+        Ok(()) => info!(duration = ?opened_at.elapsed(), "connection closed"),
+        Err(err) => warn!(%err, duration = ?opened_at.elapsed(), "connection failed"),
+    }
+}
+
+fn log_connection_task(result: std::result::Result<(), JoinError>) {
+    if let Err(err) = result {
+        error!(%err, "connection task failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        task::JoinHandle,
+    };
+    use tokio_rustls::{
+        client::TlsStream,
+        rustls::{
+            client::{ServerCertVerified, ServerCertVerifier},
+            ClientConfig, ServerName,
+        },
+        TlsConnector,
+    };
+    use tonic::transport::Server;
+
+    use super::*;
+    use crate::plugin::{proto::grpc_controller_server::GrpcControllerServer, Controller};
+
+    const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+    const EMPTY_SETTINGS_FRAME: &[u8] = &[0, 0, 0, 4, 0, 0, 0, 0, 0];
+
+    struct TestServer {
+        address: String,
+        client_cert: (Certificate, PrivateKey),
+        shutdown: CancellationToken,
+        handle: JoinHandle<Result<()>>,
+    }
+
+    /// A self-signed CA certificate, like the one Terraform generates for itself.
+    fn terraform_like_cert() -> (Certificate, PrivateKey) {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+
+        (
+            Certificate(cert.serialize_der().unwrap()),
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+    }
+
+    async fn start(limits: Limits) -> TestServer {
+        let client_cert = terraform_like_cert();
+        let (cert, key) = generate_server_cert().unwrap();
+        let tls_acceptor = tls_acceptor(cert, key, client_cert.0.clone()).unwrap();
+
+        let listener = Listener::bind_tcp().await.unwrap();
+        let address = listener.address().unwrap();
+
+        let svc = Server::builder()
+            .add_service(GrpcControllerServer::new(Controller::new(
+                CancellationToken::new(),
+            )))
+            .into_service();
+
+        let shutdown = CancellationToken::new();
+        let handle = tokio::spawn(serve_connections(
+            svc,
+            listener,
+            Some(tls_acceptor),
+            shutdown.clone(),
+            limits,
+        ));
+
+        TestServer {
+            address,
+            client_cert,
+            shutdown,
+            handle,
+        }
+    }
+
+    /// Terraform trusts the certificate from the handshake line, which is all the tests
+    /// need to know about the server.
+    struct AnyServerCert;
+
+    impl ServerCertVerifier for AnyServerCert {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> std::result::Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    async fn connect(
+        server: &TestServer,
+        client_cert: Option<(Certificate, PrivateKey)>,
+    ) -> std::io::Result<TlsStream<TcpStream>> {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AnyServerCert));
+
+        let mut config = match client_cert {
+            Some((cert, key)) => config.with_client_auth_cert(vec![cert], key).unwrap(),
+            None => config.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let stream = TcpStream::connect(&server.address).await?;
+
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+    }
+
+    /// Whether the server answers the HTTP/2 connection preface with its settings.
+    async fn speaks_http2(stream: &mut TlsStream<TcpStream>) -> bool {
+        if stream.write_all(HTTP2_PREFACE).await.is_err()
+            || stream.write_all(EMPTY_SETTINGS_FRAME).await.is_err()
+        {
+            return false;
+        }
+
+        let mut frame_header = [0u8; 9];
+        match stream.read_exact(&mut frame_header).await {
+            Ok(_) => frame_header[3] == 0x4,
+            Err(_) => false,
+        }
+    }
+
+    async fn serves_terraform(server: &TestServer) -> bool {
+        match connect(server, Some(server.client_cert.clone())).await {
+            Ok(mut stream) => speaks_http2(&mut stream).await,
+            Err(_) => false,
+        }
+    }
+
+    async fn stop(server: TestServer) {
+        server.shutdown.cancel();
+
+        timeout(Duration::from_secs(5), server.handle)
+            .await
+            .expect("server did not drain in time")
+            .expect("server task panicked")
+            .expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn survives_clients_aborting_the_handshake() {
+        let server = start(LIMITS).await;
+
+        // connects and leaves right away
+        drop(TcpStream::connect(&server.address).await.unwrap());
+
+        // sends something that isn't TLS at all
+        let mut stream = TcpStream::connect(&server.address).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        drop(stream);
+
+        // starts a TLS record and hangs up halfway through
+        let mut stream = TcpStream::connect(&server.address).await.unwrap();
+        stream
+            .write_all(&[0x16, 0x03, 0x01, 0x02, 0x00, 0x01])
+            .await
+            .unwrap();
+        drop(stream);
+
+        assert!(serves_terraform(&server).await);
+
+        stop(server).await;
+    }
+
+    #[tokio::test]
+    async fn rejects_clients_without_terraforms_certificate() {
+        let server = start(LIMITS).await;
+
+        if let Ok(mut stream) = connect(&server, None).await {
+            assert!(!speaks_http2(&mut stream).await);
+        }
+
+        if let Ok(mut stream) = connect(&server, Some(terraform_like_cert())).await {
+            assert!(!speaks_http2(&mut stream).await);
+        }
+
+        assert!(serves_terraform(&server).await);
+
+        stop(server).await;
+    }
+
+    #[tokio::test]
+    async fn times_out_stalled_handshakes_to_free_connection_slots() {
+        let handshake_timeout = Duration::from_millis(200);
+        let server = start(Limits {
+            max_connections: 1,
+            handshake_timeout,
+            ..LIMITS
+        })
+        .await;
+
+        // takes the only slot without ever starting the handshake
+        let _stalled = TcpStream::connect(&server.address).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let started = Instant::now();
+        assert!(serves_terraform(&server).await);
+        assert!(started.elapsed() >= handshake_timeout - Duration::from_millis(50));
+
+        stop(server).await;
+    }
+
+    #[tokio::test]
+    async fn drains_open_connections_on_shutdown() {
+        let server = start(Limits {
+            drain_timeout: Duration::from_millis(200),
+            ..LIMITS
+        })
+        .await;
 
-        tokio::spawn(async move {
-            let conn = tls_acceptor.accept(conn).await.unwrap();
+        // stays open while the server shuts down, without ever acknowledging it
+        let mut stream = connect(&server, Some(server.client_cert.clone()))
+            .await
+            .unwrap();
+        assert!(speaks_http2(&mut stream).await);
 
-            let svc = tower::ServiceBuilder::new().service(svc);
+        // a client stuck in the handshake does not hold up the shutdown either
+        let _stalled = TcpStream::connect(&server.address).await.unwrap();
 
-            http.serve_connection(conn, svc).await.unwrap();
-        });
+        stop(server).await;
     }
 }
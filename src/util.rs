@@ -1,52 +1,170 @@
-use anyhow::Result;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
 use rand::Rng;
-use rmp::Marker;
 use serde::{de::DeserializeOwned, Serialize};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    cty::{decode_unknown_string_values, encode_unknown_string_values},
-    server::{tf, VmResourceConfig, VmResourceState},
+    cty::{self, Value},
+    schema::Model,
+    server::tf,
 };
 
-pub const UNKNOWN_STRING: &'static str = "<unknown>";
-
+/// `len` random bytes as lowercase hex, two digits each.
 pub fn random_hex_suffix(len: usize) -> String {
     let mut rng = rand::thread_rng();
-    let mut suffix = String::with_capacity(len);
+    let mut suffix = String::with_capacity(len * 2);
 
     for _ in 0..len {
-        suffix.push_str(&format!("{:x}", rng.gen::<u8>()));
+        suffix.push_str(&format!("{:02x}", rng.gen::<u8>()));
     }
 
     suffix
 }
 
+/// Parses a duration in the format used by Terraform timeouts, like `30s`, `5m` or `1h30m`.
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid =
+        || anyhow!("invalid duration `{value}`, expected something like `30s`, `5m` or `1h30m`");
+
+    if value.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(number_len);
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+
+        let unit_len = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let unit_secs = match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(invalid()),
+        };
+
+        total = Duration::try_from_secs_f64(number * unit_secs)
+            .ok()
+            .and_then(|duration| total.checked_add(duration))
+            .ok_or_else(invalid)?;
+        rest = tail;
+    }
+
+    Ok(total)
+}
+
+pub enum PollOutcome {
+    Due,
+    TimedOut,
+    Cancelled,
+}
+
+/// Paces a wait loop, doubling the interval between polls up to `max_interval` and
+/// giving up once `timeout` has elapsed or the provider is asked to stop.
+pub struct Poller {
+    /// `None` when the timeout is too long to ever be reached.
+    deadline: Option<Instant>,
+    interval: Duration,
+    max_interval: Duration,
+    cancel: CancellationToken,
+}
+
+impl Poller {
+    pub fn new(
+        timeout: Duration,
+        interval: Duration,
+        max_interval: Duration,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self {
+            deadline: Instant::now().checked_add(timeout),
+            interval,
+            max_interval: max_interval.max(interval),
+            cancel,
+        }
+    }
+
+    /// Sleeps until the next poll is due.
+    pub async fn wait(&mut self) -> PollOutcome {
+        let mut sleep = self.interval;
+
+        if let Some(deadline) = self.deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                return PollOutcome::TimedOut;
+            }
+
+            sleep = sleep.min(deadline - now);
+        }
+
+        tokio::select! {
+            _ = self.cancel.cancelled() => return PollOutcome::Cancelled,
+            _ = tokio::time::sleep(sleep) => {}
+        };
+        self.interval = self.interval.saturating_mul(2).min(self.max_interval);
+
+        PollOutcome::Due
+    }
+}
+
+/// Adds a diagnostic to anything with a `diagnostics` list, like an RPC response or
+/// [`Diagnostics`](crate::resource::Diagnostics). The severity defaults to error.
 #[macro_export]
-macro_rules! bail_with_diagnostic {
+macro_rules! push_diagnostic {
     ($resp:ident, $summary:expr, $detail:expr, $severity:expr) => {
-        $resp.diagnostics.push(crate::server::tf::Diagnostic {
+        $resp.diagnostics.push($crate::server::tf::Diagnostic {
             severity: $severity as i32,
             summary: $summary.to_string(),
             detail: $detail.to_string(),
             ..Default::default()
         });
+    };
+    ($resp:ident, $summary:expr, $detail:expr) => {
+        $crate::push_diagnostic!(
+            $resp,
+            $summary,
+            $detail,
+            $crate::server::tf::diagnostic::Severity::Error
+        );
+    };
+}
 
-        return Ok(tonic::Response::new($resp));
+/// Adds a diagnostic to an RPC response and returns the response from the enclosing
+/// handler. The detail defaults to the summary.
+#[macro_export]
+macro_rules! bail_with_diagnostic {
+    ($resp:ident, $summary:expr, $detail:expr, $severity:expr) => {
+        $crate::push_diagnostic!($resp, $summary, $detail, $severity);
+
+        return Ok($crate::tonic::Response::new($resp));
     };
     ($resp:ident, $summary:expr, $detail:expr) => {
-        bail_with_diagnostic!(
+        $crate::bail_with_diagnostic!(
             $resp,
             $summary,
             $detail,
-            crate::server::tf::diagnostic::Severity::Error
+            $crate::server::tf::diagnostic::Severity::Error
         );
     };
     ($resp:ident, $summary:expr) => {
-        bail_with_diagnostic!(
+        $crate::bail_with_diagnostic!(
             $resp,
             $summary,
             $summary,
-            crate::server::tf::diagnostic::Severity::Error
+            $crate::server::tf::diagnostic::Severity::Error
         );
     };
 }
@@ -64,100 +182,245 @@ impl IntoDynamicValue for Vec<u8> {
     }
 }
 
-pub enum ResourceAction {
-    Create,
-    Delete,
-}
-
-pub struct ResourceState {
-    pub already_exists: bool,
-    pub did_change: bool,
-    pub action: ResourceAction,
-    pub prior_state: Option<VmResourceState>,
-    pub config: Option<VmResourceConfig>,
+/// Decodes a dynamic value from whichever of its encodings Terraform populated, msgpack or
+/// JSON. A value with neither is null.
+pub fn decode_dynamic_value(value: &tf::DynamicValue) -> Result<Value> {
+    if !value.msgpack.is_empty() {
+        Value::from_msgpack_implied(&value.msgpack)
+    } else if !value.json.is_empty() {
+        Value::from_json_implied(&value.json)
+    } else {
+        Ok(Value::Null)
+    }
 }
 
-pub fn deserialize_dynamic_value<T>(data: Vec<u8>) -> Result<T>
+pub fn deserialize_dynamic_value<T>(value: tf::DynamicValue) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let data = decode_unknown_string_values(data)?;
-    let data = rmp_serde::from_slice::<T>(data.as_slice())?;
-
-    Ok(data)
+    cty::from_value(decode_dynamic_value(&value)?)
 }
 
+/// Encodes `data` as a value of the object type its schema block declares, with every
+/// attribute of the block present.
 pub fn serialize_dynamic_value<T>(data: &T) -> Result<Vec<u8>>
 where
-    T: Serialize,
+    T: Model + Serialize,
 {
-    let data = rmp_serde::to_vec(data)?;
-    let data = encode_unknown_string_values(data)?;
+    let value = cty::to_value(data)?
+        .conform(&T::block().cty_type())
+        .context("value doesn't match the schema")?;
 
-    Ok(data)
+    Ok(value.to_msgpack())
 }
 
-pub fn compute_resource_state(
-    prior_state: Option<tf::DynamicValue>,
-    config: Option<tf::DynamicValue>,
-) -> Result<ResourceState> {
-    let prior_state_bytes = prior_state
-        .unwrap_or(tf::DynamicValue {
-            msgpack: vec![Marker::Null.to_u8()],
-            json: vec![],
-        })
-        .msgpack;
+pub fn deserialize_optional_dynamic_value<T>(value: Option<tf::DynamicValue>) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    match decode_dynamic_value(&value.unwrap_or_default())? {
+        Value::Null => Ok(None),
+        value => Ok(Some(cty::from_value(value)?)),
+    }
+}
 
-    let prior_state_exists =
-        prior_state_bytes.len() > 1 && prior_state_bytes[0] != Marker::Null.to_u8();
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
 
-    let prior_state = if prior_state_exists {
-        Some(deserialize_dynamic_value::<VmResourceState>(
-            prior_state_bytes.clone(),
-        )?)
-    } else {
-        None
+    use super::*;
+    use crate::{
+        cty::{MaybeUnknown, Type},
+        model,
+        schema::{Attribute, Block},
     };
 
-    let config_bytes = config
-        .unwrap_or(tf::DynamicValue {
-            msgpack: vec![Marker::Null.to_u8()],
-            json: vec![],
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Network {
+        cidr: String,
+        ports: Vec<u16>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct State {
+        name: String,
+        size: i64,
+        ratio: f64,
+        public: Option<bool>,
+        id: MaybeUnknown<String>,
+        tags: Vec<String>,
+        network: Network,
+    }
+
+    /// The same value, encoded both ways Terraform may send it.
+    fn encodings(value: serde_json::Value) -> [tf::DynamicValue; 2] {
+        [
+            tf::DynamicValue {
+                msgpack: Value::from(value.clone()).to_msgpack(),
+                json: vec![],
+            },
+            tf::DynamicValue {
+                msgpack: vec![],
+                json: value.to_string().into_bytes(),
+            },
+        ]
+    }
+
+    #[test]
+    fn decodes_msgpack_and_json_alike() {
+        let [msgpack, json] = encodings(json!({
+            "name": "vm",
+            "size": 2,
+            "ratio": 0.5,
+            "public": null,
+            "id": "vm-1",
+            "tags": ["a", "b"],
+            "network": {"cidr": "10.0.0.0/16", "ports": [22, 443]},
+        }));
+
+        assert_eq!(
+            decode_dynamic_value(&msgpack).unwrap(),
+            decode_dynamic_value(&json).unwrap()
+        );
+
+        let expected = State {
+            name: "vm".to_string(),
+            size: 2,
+            ratio: 0.5,
+            public: None,
+            id: MaybeUnknown::Known("vm-1".to_string()),
+            tags: vec!["a".to_string(), "b".to_string()],
+            network: Network {
+                cidr: "10.0.0.0/16".to_string(),
+                ports: vec![22, 443],
+            },
+        };
+        assert_eq!(
+            deserialize_dynamic_value::<State>(msgpack).unwrap(),
+            expected
+        );
+        assert_eq!(deserialize_dynamic_value::<State>(json).unwrap(), expected);
+    }
+
+    #[test]
+    fn decodes_null_from_either_encoding_or_neither() {
+        for value in encodings(serde_json::Value::Null)
+            .into_iter()
+            .chain([tf::DynamicValue::default()])
+        {
+            assert_eq!(decode_dynamic_value(&value).unwrap(), Value::Null);
+            assert_eq!(
+                deserialize_optional_dynamic_value::<State>(Some(value)).unwrap(),
+                None
+            );
+        }
+
+        assert_eq!(
+            deserialize_optional_dynamic_value::<State>(None).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_durations_too_long_to_represent() {
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("9999999999999999h").is_err());
+        assert!(parse_duration("18446744073709551615s1h").is_err());
+    }
+
+    #[tokio::test]
+    async fn polls_with_huge_timeouts_and_intervals() {
+        let mut poller = Poller::new(
+            Duration::MAX,
+            Duration::from_millis(1),
+            Duration::MAX,
+            CancellationToken::new(),
+        );
+
+        assert!(matches!(poller.wait().await, PollOutcome::Due));
+        poller.interval = Duration::MAX;
+        poller.cancel.cancel();
+        assert!(matches!(poller.wait().await, PollOutcome::Cancelled));
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        let value = tf::DynamicValue {
+            msgpack: vec![],
+            json: b"{\"name\":".to_vec(),
+        };
+
+        assert!(decode_dynamic_value(&value).is_err());
+    }
+
+    model! {
+        #[schema]
+        #[derive(Debug, PartialEq, Serialize)]
+        struct Resource {
+            #[schema(required)]
+            name: String,
+            #[schema(optional)]
+            public: Option<bool>,
+            #[schema(computed)]
+            id: MaybeUnknown<String>,
+        }
+    }
+
+    #[test]
+    fn serializes_every_attribute_of_the_schema() {
+        let bytes = serialize_dynamic_value(&Resource {
+            name: "vm".to_string(),
+            public: None,
+            id: MaybeUnknown::Unknown,
         })
-        .msgpack;
+        .unwrap();
 
-    let config_exists = config_bytes.len() > 1 && config_bytes[0] != Marker::Null.to_u8();
+        let value = Value::from_msgpack(&bytes, &Resource::block().cty_type()).unwrap();
+        assert_eq!(
+            value,
+            Value::Object(
+                [
+                    ("id", Value::unknown()),
+                    ("name", Value::String("vm".to_string())),
+                    ("public", Value::Null),
+                ]
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect()
+            )
+        );
 
-    let config = if config_exists {
-        Some(deserialize_dynamic_value::<VmResourceConfig>(
-            config_bytes.clone(),
-        )?)
-    } else {
-        None
-    };
+        let bytes = serialize_dynamic_value(&None::<Resource>).unwrap();
+        assert_eq!(Value::from_msgpack_implied(&bytes).unwrap(), Value::Null);
+    }
 
-    let did_config_change = if !prior_state_exists || !config_exists {
-        true
-    } else {
-        let prior_state = deserialize_dynamic_value::<VmResourceState>(prior_state_bytes)?;
-        let config = deserialize_dynamic_value::<VmResourceConfig>(config_bytes)?;
+    #[test]
+    fn reports_values_that_dont_match_the_schema() {
+        #[derive(Serialize)]
+        struct Mismatched {
+            size: String,
+        }
 
-        config != prior_state.config
-    };
+        impl Model for Mismatched {
+            fn block() -> Block {
+                let mut block = Block::new(&[]);
+                block.attribute(Attribute::new::<i64>("size", &[]).required());
+                block
+            }
+        }
 
-    let action = if prior_state_exists && !config_exists {
-        ResourceAction::Delete
-    } else if !prior_state_exists && config_exists {
-        ResourceAction::Create
-    } else {
-        ResourceAction::Create
-    };
+        let err = serialize_dynamic_value(&Mismatched {
+            size: "large".to_string(),
+        })
+        .unwrap_err();
 
-    Ok(ResourceState {
-        already_exists: prior_state_exists,
-        did_change: did_config_change,
-        action,
-        prior_state,
-        config,
-    })
+        assert_eq!(
+            format!("{err:#}"),
+            format!(
+                "value doesn't match the schema: in `size`: expected a value of type {}, got string",
+                Type::Number
+            )
+        );
+    }
 }